@@ -1,6 +1,132 @@
 use chrono::{DateTime, Utc};
+use clap::builder::{TypedValueParser, ValueParser};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+/// The character typically used to separate path components
+/// in environment variables.
+const ENV_PATH_SEP: char = if cfg!(windows) { ';' } else { ':' };
+
+/// An mdBook backend that converts books to Typst and PDF.
+///
+/// Without a subcommand, the mdBook render context is read from stdin as
+/// `mdbook build` passes it to backends.
+#[derive(Debug, Clone, Parser)]
+#[clap(name = "mdbook-typst-pdf", version, about)]
+pub struct CliArguments {
+  /// The command to run
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+/// What to do.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+  /// Converts a book to Typst and compiles it to PDF if enabled in book.toml
+  Build(BookCommand),
+
+  /// Converts a book to a Typst file without compiling it
+  Convert(BookCommand),
+
+  /// Compiles a Typst file to PDF
+  Compile(CompileCommand),
+
+  /// Builds a book and rebuilds it whenever its sources change
+  Watch(BookCommand),
+
+  /// Lists all discovered fonts in system and custom font paths
+  Fonts(FontsCommand),
+}
+
+/// Arguments of the commands working on a whole book.
+#[derive(Debug, Clone, Args)]
+pub struct BookCommand {
+  /// Root directory of the book, containing book.toml
+  #[clap(default_value = ".", value_name = "BOOK_DIR")]
+  pub book_dir: PathBuf,
+
+  /// Output directory, defaults to the build directory configured in book.toml
+  #[clap(long, short, value_name = "DIR")]
+  pub dest_dir: Option<PathBuf>,
+
+  /// Arguments for compiling the converted Typst file
+  #[clap(flatten)]
+  pub world_args: WorldArgs,
+}
+
+/// Compiles a Typst file.
+#[derive(Debug, Clone, Args)]
+pub struct CompileCommand {
+  /// Path to input Typst file. Use `-` to read input from stdin
+  #[clap(value_parser = input_value_parser())]
+  pub input: Input,
+
+  /// Path to output PDF file, defaults to the input with a `.pdf` extension
+  pub output: Option<PathBuf>,
+
+  /// Arguments for compiling the Typst file
+  #[clap(flatten)]
+  pub world_args: WorldArgs,
+}
+
+/// Lists all discovered fonts in system and custom font paths.
+#[derive(Debug, Clone, Args)]
+pub struct FontsCommand {
+  /// Common font arguments
+  #[clap(flatten)]
+  pub font_args: FontArgs,
+
+  /// Also lists style variants of each font family
+  #[arg(long)]
+  pub variants: bool,
+}
+
+/// Arguments configuring how a Typst file is compiled.
+#[derive(Debug, Clone, Default, Args)]
+pub struct WorldArgs {
+  /// Configures the project root (for absolute paths)
+  #[clap(long = "root", env = "TYPST_ROOT", value_name = "DIR")]
+  pub root: Option<PathBuf>,
+
+  /// Add a string key-value pair visible through `sys.inputs`
+  #[clap(
+    long = "input",
+    value_name = "key=value",
+    action = ArgAction::Append,
+    value_parser = ValueParser::new(parse_sys_input_pair),
+  )]
+  pub inputs: Vec<(String, String)>,
+
+  /// Common font arguments
+  #[clap(flatten)]
+  pub font_args: FontArgs,
+
+  /// The document's creation date formatted as a UNIX timestamp.
+  ///
+  /// For more information, see <https://reproducible-builds.org/specs/source-date-epoch/>.
+  #[clap(
+    long = "creation-timestamp",
+    env = "SOURCE_DATE_EPOCH",
+    value_name = "UNIX_TIMESTAMP",
+    value_parser = parse_timestamp,
+  )]
+  pub creation_timestamp: Option<DateTime<Utc>>,
+
+  /// Arguments related to storage of packages in the system
+  #[clap(flatten)]
+  pub package_storage_args: PackageStorageArgs,
+
+  /// One (or multiple comma-separated) PDF standards that Typst will enforce
+  /// conformance with
+  #[arg(long = "pdf-standard", value_delimiter = ',')]
+  pub pdf_standard: Vec<PdfStandard>,
+
+  /// The format to emit diagnostics in
+  #[clap(long, value_enum)]
+  pub diagnostic_format: Option<DiagnosticFormat>,
+}
+
 /// Common arguments of compile, watch, and query.
 #[derive(Debug, Clone)]
 pub struct SharedArgs {
@@ -24,46 +150,150 @@ pub struct SharedArgs {
   /// Arguments related to storage of packages in the system
   pub package_storage_args: PackageStorageArgs,
 
+  /// PDF standards that the output must conform to
+  pub pdf_standards: Vec<PdfStandard>,
+
+  /// The format to emit diagnostics in
+  pub diagnostic_format: DiagnosticFormat,
+
+  /// A stable identifier for the PDF document, derived from the document's
+  /// title and author if not set
+  pub ident: Option<String>,
+
   pub output: PathBuf,
 }
 
+impl SharedArgs {
+  /// Compile `input` to `output` in the world configured by `world_args`.
+  pub fn new(input: Input, output: PathBuf, world_args: WorldArgs) -> Self {
+    Self {
+      input,
+      root: world_args.root,
+      inputs: world_args.inputs,
+      font_args: world_args.font_args,
+      creation_timestamp: world_args.creation_timestamp,
+      package_storage_args: world_args.package_storage_args,
+      pdf_standards: world_args.pdf_standard,
+      diagnostic_format: world_args.diagnostic_format.unwrap_or_default(),
+      ident: None,
+      output,
+    }
+  }
+}
+
 /// Which format to use for diagnostics.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(
+  Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
 pub enum DiagnosticFormat {
+  #[default]
   Human,
   Short,
+  /// One JSON object per line.
+  Json,
+}
+
+/// A PDF standard that Typst can enforce conformance with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum PdfStandard {
+  /// PDF 1.7.
+  #[value(name = "1.7")]
+  #[serde(rename = "1.7")]
+  V_1_7,
+  /// PDF/A-2b.
+  #[value(name = "a-2b")]
+  #[serde(rename = "a-2b")]
+  A_2b,
+}
+
+impl PdfStandard {
+  /// Whether images need alt text to conform to the standard.
+  pub fn requires_alt_text(self) -> bool {
+    matches!(self, PdfStandard::A_2b)
+  }
 }
 
 /// An input that is either stdin or a real path.
 #[derive(Debug, Clone)]
 pub enum Input {
   /// Stdin, represented by `-`.
-  #[allow(dead_code)]
   Stdin,
   /// A non-empty path.
   Path(PathBuf),
 }
 
 /// Arguments related to where packages are stored in the system.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct PackageStorageArgs {
   /// Custom path to local packages, defaults to system-dependent location
+  #[clap(long = "package-path", env = "TYPST_PACKAGE_PATH", value_name = "DIR")]
   pub package_path: Option<PathBuf>,
 
   /// Custom path to package cache, defaults to system-dependent location
+  #[clap(
+    long = "package-cache-path",
+    env = "TYPST_PACKAGE_CACHE_PATH",
+    value_name = "DIR"
+  )]
   pub package_cache_path: Option<PathBuf>,
 }
 
 /// Common arguments to customize available fonts
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct FontArgs {
   /// Adds additional directories that are recursively searched for fonts
   ///
   /// If multiple paths are specified, they are separated by the system's path
   /// separator (`:` on Unix-like systems and `;` on Windows).
+  #[clap(
+    long = "font-path",
+    env = "TYPST_FONT_PATHS",
+    value_name = "DIR",
+    value_delimiter = ENV_PATH_SEP,
+  )]
   pub font_paths: Vec<PathBuf>,
 
   /// Ensures system fonts won't be searched, unless explicitly included via
   /// `--font-path`
+  #[arg(long)]
   pub ignore_system_fonts: bool,
 }
+
+/// The clap value parser used by `CompileCommand.input`
+fn input_value_parser() -> impl TypedValueParser<Value = Input> {
+  clap::builder::OsStringValueParser::new().try_map(|value| {
+    if value.is_empty() {
+      Err(clap::Error::new(clap::error::ErrorKind::InvalidValue))
+    } else if value == "-" {
+      Ok(Input::Stdin)
+    } else {
+      Ok(Input::Path(value.into()))
+    }
+  })
+}
+
+/// Parses key/value pairs split by the first equal sign.
+///
+/// This function will return an error if the argument contains no equals sign
+/// or contains the key (before the equals sign) is empty.
+fn parse_sys_input_pair(raw: &str) -> Result<(String, String), String> {
+  let (key, val) = raw
+    .split_once('=')
+    .ok_or("input must be a key and a value separated by an equal sign")?;
+  let key = key.trim().to_owned();
+  if key.is_empty() {
+    return Err("the key was missing or empty".to_owned());
+  }
+  let val = val.trim().to_owned();
+  Ok((key, val))
+}
+
+/// Parses a UNIX timestamp according to <https://reproducible-builds.org/specs/source-date-epoch/>
+pub fn parse_timestamp(raw: &str) -> Result<DateTime<Utc>, String> {
+  let timestamp: i64 = raw
+    .parse()
+    .map_err(|err| format!("timestamp must be decimal integer ({err})"))?;
+  DateTime::from_timestamp(timestamp, 0).ok_or_else(|| "timestamp out of range".to_string())
+}
@@ -1,3 +1,6 @@
+/**** MDBOOK_TYPST_PDF_VERSION 2 ****/
+#import "mdbook.typ": book
+
 #set text(
   lang: "ru",
   font: (
@@ -28,28 +31,141 @@
   radius: 4pt,
 )
 
+#let mdbook-callout-styles = (
+  note: (color: rgb("#0969da"), icon: "ℹ", title: "Note"),
+  tip: (color: rgb("#1a7f37"), icon: "💡", title: "Tip"),
+  important: (color: rgb("#8250df"), icon: "❗", title: "Important"),
+  warning: (color: rgb("#9a6700"), icon: "⚠", title: "Warning"),
+  caution: (color: rgb("#d1242f"), icon: "⛔", title: "Caution"),
+  abstract: (color: rgb("#00b0ff"), icon: "📋", title: "Abstract"),
+  info: (color: rgb("#00b8d4"), icon: "ℹ", title: "Info"),
+  success: (color: rgb("#00c853"), icon: "✔", title: "Success"),
+  question: (color: rgb("#64dd17"), icon: "❓", title: "Question"),
+  failure: (color: rgb("#ff5252"), icon: "✘", title: "Failure"),
+  danger: (color: rgb("#ff1744"), icon: "⚡", title: "Danger"),
+  bug: (color: rgb("#f50057"), icon: "🐞", title: "Bug"),
+  example: (color: rgb("#7c4dff"), icon: "📝", title: "Example"),
+  quote: (color: rgb("#9e9e9e"), icon: "❝", title: "Quote"),
+)
+
+#let mdbook-callout(kind: "note", title: auto, body) = {
+  let style = mdbook-callout-styles.at(kind, default: mdbook-callout-styles.note)
+  let title = if title == auto { style.title } else { title }
+
+  block(
+    width: 100%,
+    fill: style.color.lighten(90%),
+    stroke: (left: 3pt + style.color),
+    inset: 10pt,
+    radius: 4pt,
+    {
+      if title != none {
+        text(fill: style.color, weight: "bold")[#style.icon #title]
+        parbreak()
+      }
+      body
+    },
+  )
+}
+
+// Fenced code blocks. `classes`, `id` and `attrs` are the attributes of the
+// info string, `hidden-lines` the lines mdBook hides when they are dimmed.
+#let mdbook-code(
+  lang: none,
+  classes: (),
+  id: none,
+  attrs: (:),
+  line-numbers: false,
+  highlighted-lines: (),
+  hidden-lines: (),
+  body,
+) = {
+  show raw.line: it => {
+    let line = if it.number in hidden-lines {
+      text(fill: luma(160), it.text)
+    } else {
+      it
+    }
+    if it.number in highlighted-lines {
+      line = highlight(fill: rgb("#fff5b1"), line)
+    }
+    if line-numbers {
+      box(width: 2em, align(right, text(fill: luma(160), str(it.number))))
+      h(1em)
+    }
+    line
+  }
+  body
+}
+
+// Whether the current page is the title page, which is marked with the
+// `<mdbook-title-page>` label and has no header and footer.
+#let mdbook-is-title-page() = query(<mdbook-title-page>).any(it => (
+  it.location().page() == here().page()
+))
+
 #set page(
+  numbering: "i",
   header: context {
-    if counter(page).get().first() > 1 [
-      MDBOOK_TYPST_PDF_TITLE
+    if not mdbook-is-title-page() [
+      #book.title
     ]
   },
   footer: context {
-    if counter(page).get().first() > 1 [
-      #counter(page).display(
-        "1/1",
-        both: true,
-      )
+    if not mdbook-is-title-page() [
+      #counter(page).display(page.numbering)
     ]
   },
 )
 
+// Unnumbered chapters before the first numbered chapter, numbered in roman
+// numerals after the outline.
+#let mdbook-frontmatter(body) = {
+  set page(numbering: "i")
+  body
+}
+
+// Numbered chapters and parts, numbered from 1.
+#let mdbook-mainmatter(body) = {
+  set page(numbering: "1")
+  counter(page).update(1)
+  body
+}
+
+// Unnumbered chapters after the last numbered chapter, numbered as appendices
+// and continuing the page numbers of the main matter.
+#let mdbook-backmatter(body) = {
+  set page(numbering: "1")
+  counter(heading).update(0)
+  set heading(numbering: "A.1", supplement: [Appendix])
+  body
+}
+
+#let mdbook-part(title) = {
+  pagebreak(weak: true)
+  v(1fr)
+  {
+    show heading: set text(size: 24pt)
+    align(center, heading(numbering: none, level: 1, outlined: true, title))
+  }
+  v(1fr)
+  pagebreak(weak: true)
+}
+
+#let mdbook-draft(title) = {
+  heading(numbering: none, level: 1, outlined: false, title)
+  emph[This chapter has not been written yet.]
+}
+
+/**** MDBOOK_TYPST_PDF_BODY ****/
+
 #align(center, text(17pt)[
-  *MDBOOK_TYPST_PDF_TITLE*
-])
+  *#book.title*
+]) #metadata(none) <mdbook-title-page>
 
 #pagebreak()
+#counter(page).update(1)
 #outline(depth: 2, indent: 1em)
 #pagebreak()
 
-/**** MDBOOK_TYPST_PDF_PLACEHOLDER ****/
+#body
@@ -0,0 +1,42 @@
+use mdbook::renderer::{RenderContext, Renderer};
+use mdbook::MDBook;
+use std::path::Path;
+
+use crate::args::WorldArgs;
+
+/// The name mdBook knows this backend by, as in `[output.typst-pdf]`.
+pub const RENDERER_NAME: &str = "typst-pdf";
+
+/// This backend as an in-process mdBook renderer.
+pub struct TypstPdfRenderer;
+
+impl Renderer for TypstPdfRenderer {
+  fn name(&self) -> &str {
+    RENDERER_NAME
+  }
+
+  fn render(&self, ctx: &RenderContext) -> Result<(), anyhow::Error> {
+    crate::render(ctx, &WorldArgs::default())
+  }
+}
+
+/// Load and preprocess the book in `book_dir` the way `mdbook build` does
+/// before handing it to a renderer. The output goes to `dest_dir` if given,
+/// otherwise to the build directory configured in `book.toml`.
+pub fn load(book_dir: &Path, dest_dir: Option<&Path>) -> Result<RenderContext, anyhow::Error> {
+  let md = MDBook::load(book_dir)?;
+
+  let (book, _) = md.preprocess_book(&TypstPdfRenderer)?;
+
+  let destination = match dest_dir {
+    Some(dest_dir) => dest_dir.to_path_buf(),
+    None => md.build_dir_for(RENDERER_NAME),
+  };
+
+  Ok(RenderContext::new(
+    md.root.clone(),
+    book,
+    md.config.clone(),
+    destination,
+  ))
+}
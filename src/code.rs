@@ -0,0 +1,318 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::OnceLock;
+
+use crate::convert::typst_string;
+
+static RUST_HIDDEN_LINE_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// The Ferris images bundled for the code block attributes the Rust book
+/// marks with one.
+pub const FERRIS: &[(&str, &[u8])] = &[
+  (
+    "does_not_compile",
+    include_bytes!("assets/ferris/does_not_compile.svg"),
+  ),
+  (
+    "not_desired_behavior",
+    include_bytes!("assets/ferris/not_desired_behavior.svg"),
+  ),
+  ("panics", include_bytes!("assets/ferris/panics.svg")),
+];
+
+/// A badge shown at code blocks with a certain attribute, configured in
+/// `[output.typst-pdf.badges]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Badge {
+  /// `false` turns off the bundled Ferris image of an attribute.
+  Enabled(bool),
+  /// An image, relative to the book's `src` directory.
+  Image { image: String },
+  /// A text label.
+  Text { text: String },
+}
+
+/// Attributes of rustdoc and mdBook that make a code block without a
+/// language a Rust code block.
+const RUST_ATTRIBUTES: &[&str] = &[
+  "ignore",
+  "should_panic",
+  "no_run",
+  "compile_fail",
+  "test_harness",
+  "edition2015",
+  "edition2018",
+  "edition2021",
+  "edition2024",
+  "editable",
+  "noplayground",
+  "mdbook-runnable",
+];
+
+/// The attributes of a fenced code block, parsed from its info string.
+///
+/// Both rustdoc-style (`rust,ignore`, `rust edition2021`) and Markdown-style
+/// (`{.rust #id linenos=true}`) attributes are accepted.
+#[derive(Debug, Default, PartialEq)]
+pub struct CodeInfo {
+  /// The language, the first word or class of the info string.
+  pub lang: Option<String>,
+  /// The remaining words and classes, like `ignore` or `editable`.
+  pub classes: Vec<String>,
+  /// The `#id` attribute.
+  pub id: Option<String>,
+  /// `key=value` attributes, like `hidelines` or `hl_lines`.
+  pub attrs: BTreeMap<String, String>,
+}
+
+impl CodeInfo {
+  /// Whether the class `class` is set.
+  pub fn has_class(&self, class: &str) -> bool {
+    self.classes.iter().any(|c| c == class)
+  }
+
+  /// The prefix of hidden lines set with `hidelines=`.
+  pub fn hidelines(&self) -> Option<&str> {
+    self.attrs.get("hidelines").map(String::as_str)
+  }
+
+  /// Whether lines are numbered, with `linenos` or `linenos=true`.
+  pub fn line_numbers(&self) -> bool {
+    self.has_class("linenos")
+      || self
+        .attrs
+        .get("linenos")
+        .is_some_and(|value| matches!(value.as_str(), "true" | "yes" | "1"))
+  }
+
+  /// The 1-based numbers of the lines highlighted with `hl_lines="1 3-5"`.
+  pub fn highlighted_lines(&self) -> Vec<usize> {
+    let Some(hl_lines) = self.attrs.get("hl_lines") else {
+      return vec![];
+    };
+
+    hl_lines
+      .split(|c: char| c.is_whitespace() || c == ',')
+      .filter_map(|range| match range.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => {
+          let line = range.trim().parse().ok()?;
+          Some(line..=line)
+        }
+      })
+      .flatten()
+      .collect()
+  }
+
+  /// The arguments of the `mdbook-code` template function for this code
+  /// block, with the numbers of the lines to dim.
+  pub fn typst_args(&self, hidden_lines: &[usize]) -> String {
+    let mut args = vec![format!(
+      "lang: {}",
+      self
+        .lang
+        .as_deref()
+        .map_or("none".to_string(), typst_string)
+    )];
+
+    if !self.classes.is_empty() {
+      let classes: String = self
+        .classes
+        .iter()
+        .map(|class| format!("{}, ", typst_string(class)))
+        .collect();
+
+      args.push(format!("classes: ({})", classes));
+    }
+
+    if let Some(id) = &self.id {
+      args.push(format!("id: {}", typst_string(id)));
+    }
+
+    if !self.attrs.is_empty() {
+      let attrs: String = self
+        .attrs
+        .iter()
+        .map(|(key, value)| format!("{}: {}, ", typst_string(key), typst_string(value)))
+        .collect();
+
+      args.push(format!("attrs: ({})", attrs));
+    }
+
+    if self.line_numbers() {
+      args.push("line-numbers: true".to_string());
+    }
+
+    let numbers =
+      |lines: &[usize]| -> String { lines.iter().map(|line| format!("{}, ", line)).collect() };
+
+    let highlighted_lines = self.highlighted_lines();
+
+    if !highlighted_lines.is_empty() {
+      args.push(format!(
+        "highlighted-lines: ({})",
+        numbers(&highlighted_lines)
+      ));
+    }
+
+    if !hidden_lines.is_empty() {
+      args.push(format!("hidden-lines: ({})", numbers(hidden_lines)));
+    }
+
+    args.join(", ")
+  }
+}
+
+/// Parse the info string of a fenced code block.
+pub fn parse_info(info: &str) -> CodeInfo {
+  let mut code_info = CodeInfo::default();
+
+  for token in info_tokens(info) {
+    if let Some(id) = token.strip_prefix('#') {
+      code_info.id = Some(id.to_string());
+    } else if let Some((key, value)) = token.split_once('=') {
+      code_info.attrs.insert(key.to_string(), value.to_string());
+    } else {
+      let class = token.strip_prefix('.').unwrap_or(&token);
+
+      if code_info.lang.is_none()
+        && code_info.classes.is_empty()
+        && !RUST_ATTRIBUTES.contains(&class)
+      {
+        code_info.lang = Some(class.to_string());
+      } else {
+        code_info.classes.push(class.to_string());
+      }
+    }
+  }
+
+  // rustdoc treats code blocks with only attributes as Rust.
+  if code_info.lang.is_none()
+    && code_info
+      .classes
+      .iter()
+      .any(|class| RUST_ATTRIBUTES.contains(&class.as_str()))
+  {
+    code_info.lang = Some("rust".to_string());
+  }
+
+  code_info
+}
+
+/// Split an info string at whitespace, commas and braces, keeping quoted
+/// values together and dropping the quotes.
+fn info_tokens(info: &str) -> Vec<String> {
+  let mut tokens = vec![];
+  let mut token = String::new();
+  let mut quote = None;
+
+  for c in info.chars() {
+    match quote {
+      Some(q) if c == q => quote = None,
+      Some(_) => token.push(c),
+      None if c == '"' || c == '\'' => quote = Some(c),
+      None if c.is_whitespace() || matches!(c, ',' | '{' | '}') => {
+        if !token.is_empty() {
+          tokens.push(mem::take(&mut token));
+        }
+      }
+      None => token.push(c),
+    }
+  }
+
+  if !token.is_empty() {
+    tokens.push(token);
+  }
+
+  tokens
+}
+
+/// How lines mdBook hides in code blocks are rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HiddenLines {
+  /// Leave them out, as mdBook shows code blocks by default.
+  #[default]
+  Hide,
+  /// Show them in a lighter colour.
+  Dim,
+  /// Show them like any other line, as mdBook does when expanded.
+  Show,
+}
+
+/// A line of a code block.
+#[derive(Debug)]
+pub struct CodeLine<'a> {
+  /// The line without the hidden-line marker.
+  pub text: Cow<'a, str>,
+  /// Whether mdBook hides the line.
+  pub hidden: bool,
+}
+
+/// Split `code` into lines and mark those mdBook hides: for Rust, lines
+/// starting with `#` followed by a space or nothing, with `##` escaping a
+/// literal `#`; for other languages, lines starting with `prefix`.
+pub fn code_lines<'a>(code: &'a str, lang: &str, prefix: Option<&str>) -> Vec<CodeLine<'a>> {
+  match prefix {
+    Some(prefix) => code
+      .lines()
+      .map(|line| match line.trim_start().strip_prefix(prefix) {
+        Some(rest) => CodeLine {
+          text: format!("{}{}", &line[..line.len() - line.trim_start().len()], rest).into(),
+          hidden: true,
+        },
+        None => CodeLine {
+          text: line.into(),
+          hidden: false,
+        },
+      })
+      .collect(),
+    None if lang == "rust" => {
+      let regex = RUST_HIDDEN_LINE_REGEX.get_or_init(|| Regex::new(r"^(\s*)#(.?)(.*)$").unwrap());
+
+      code
+        .lines()
+        .map(|line| {
+          let Some(caps) = regex.captures(line) else {
+            return CodeLine {
+              text: line.into(),
+              hidden: false,
+            };
+          };
+
+          match &caps[2] {
+            // `##` is an escaped `#`.
+            "#" => CodeLine {
+              text: format!("{}#{}", &caps[1], &caps[3]).into(),
+              hidden: false,
+            },
+            // Inner and outer attributes stay visible.
+            "!" | "[" => CodeLine {
+              text: line.into(),
+              hidden: false,
+            },
+            " " | "" => CodeLine {
+              text: format!("{}{}", &caps[1], &caps[3]).into(),
+              hidden: true,
+            },
+            other => CodeLine {
+              text: format!("{}{}{}", &caps[1], other, &caps[3]).into(),
+              hidden: true,
+            },
+          }
+        })
+        .collect()
+    }
+    None => code
+      .lines()
+      .map(|line| CodeLine {
+        text: line.into(),
+        hidden: false,
+      })
+      .collect(),
+  }
+}
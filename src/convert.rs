@@ -1,18 +1,50 @@
 use anyhow::anyhow;
-use html5ever::parse_document;
-use html5ever::tendril::TendrilSink;
-use markup5ever_rcdom::{NodeData, RcDom};
 use mdbook::renderer::RenderContext;
 use mdbook::BookItem;
-use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{
+  Alignment, BlockQuoteKind, CodeBlockKind, Event, Options, Parser, Tag, TagEnd,
+};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Write;
 use std::fs;
+use std::mem;
+use std::ops::Range;
+use std::path::{Component, Path};
 use std::sync::OnceLock;
-
+use typst::utils::hash128;
+
+use crate::args::PdfStandard;
+use crate::code::{self, Badge, CodeInfo, HiddenLines};
+use crate::diagnostic;
+use crate::html::{self, HtmlContext, HtmlConverter, HtmlTag};
+use crate::images::{self, ImageFiles};
+use crate::link::LinkResolver;
+use crate::math;
+use crate::sourcemap::SourceMap;
+use crate::template::{Template, TemplateVersion, BODY_MARKER, PLACEHOLDER, TITLE_PLACEHOLDER};
 use crate::Config;
 
 static EMAIL_REGEX: OnceLock<Regex> = OnceLock::new();
+static ADMONISH_ATTR_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// Markdown events together with their source ranges.
+type SourceEvents<'a> = Vec<(Event<'a>, Range<usize>)>;
+
+/// What an image's figure caption is taken from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageCaption {
+  /// The Markdown title or the `title` attribute of `<img>`.
+  #[default]
+  Title,
+  /// The alt text.
+  Alt,
+  /// No caption.
+  None,
+}
 
 #[derive(Debug, PartialEq)]
 pub enum EventType {
@@ -21,15 +53,71 @@ pub enum EventType {
   List,
   NumberedList,
   TableHead,
-  Image,
   Heading,
+  Footnote(String),
+}
+
+/// Chapters converted by a previous build, reused while watching.
+#[derive(Default)]
+pub struct ChapterCache {
+  /// Fingerprint of the book-wide state every chapter's conversion depends on.
+  fingerprint: u128,
+  /// Chapters converted in the previous build, by a hash of their inputs.
+  previous: HashMap<u128, CachedChapter>,
+  /// Chapters converted in the current build, by a hash of their inputs.
+  current: HashMap<u128, CachedChapter>,
+}
+
+/// A converted chapter and the image files it read and wrote.
+struct CachedChapter {
+  converted: (String, SourceMap),
+  images: ImageFiles,
+}
+
+impl ChapterCache {
+  /// Start a new build, forgetting everything if the book-wide state changed.
+  fn begin(&mut self, fingerprint: u128) {
+    let previous = mem::take(&mut self.current);
+
+    self.previous = if fingerprint == self.fingerprint {
+      previous
+    } else {
+      HashMap::new()
+    };
+
+    self.fingerprint = fingerprint;
+  }
+
+  /// Reuse the conversion of unchanged inputs and images or convert them now.
+  fn get_or_convert(
+    &mut self,
+    key: u128,
+    convert: impl FnOnce(&ImageFiles) -> Result<(String, SourceMap), anyhow::Error>,
+  ) -> Result<(String, SourceMap), anyhow::Error> {
+    let cached = match self.previous.remove(&key) {
+      Some(cached) if cached.images.unchanged() => cached,
+      _ => {
+        let images = ImageFiles::default();
+        let converted = convert(&images)?;
+
+        CachedChapter { converted, images }
+      }
+    };
+
+    let converted = cached.converted.clone();
+
+    self.current.insert(key, cached);
+
+    Ok(converted)
+  }
 }
 
 pub fn convert_typst(
   ctx: &RenderContext,
   cfg: &Config,
-  template: &str,
-) -> Result<String, anyhow::Error> {
+  template: &Template,
+  cache: &mut ChapterCache,
+) -> Result<(String, SourceMap), anyhow::Error> {
   let title = ctx
     .config
     .book
@@ -37,107 +125,528 @@ pub fn convert_typst(
     .as_ref()
     .ok_or(anyhow!("title not found"))?;
 
-  let mut output_template = template.to_owned().replace("MDBOOK_TYPST_PDF_TITLE", title);
+  let mut output_template = match template.version {
+    TemplateVersion::V1 => template.source.replace(TITLE_PLACEHOLDER, title),
+    TemplateVersion::V2 => template.source.clone(),
+  };
 
   let mut typst_str = String::new();
 
-  for item in ctx.book.iter() {
-    writeln!(typst_str, "{}", convert_book_item(ctx, cfg, item)?)?;
+  let mut source_map = SourceMap::default();
+
+  let links = LinkResolver::new(ctx);
+
+  cache.begin(hash128(&(
+    serde_json::to_string(cfg)?,
+    serde_json::to_string(&ctx.config)?,
+    links.fingerprint(),
+  )));
+
+  let sections = &ctx.book.sections;
+
+  // mdBook has no explicit prefix and suffix chapters, they are the unnumbered
+  // chapters before the first and after the last numbered chapter or part.
+  let is_main_matter = |item: &BookItem| match item {
+    BookItem::Chapter(ch) => ch.number.is_some(),
+    BookItem::PartTitle(_) => true,
+    BookItem::Separator => false,
+  };
+
+  let main_start = sections.iter().position(is_main_matter).unwrap_or(0);
+  let main_end = sections
+    .iter()
+    .rposition(is_main_matter)
+    .map_or(sections.len(), |i| i + 1);
+
+  // Part titles take the top level of the outline, chapters move one down.
+  let has_parts = sections
+    .iter()
+    .any(|item| matches!(item, BookItem::PartTitle(_)));
+
+  let matters = [
+    ("mdbook-frontmatter", &sections[..main_start], 0),
+    (
+      "mdbook-mainmatter",
+      &sections[main_start..main_end],
+      usize::from(has_parts),
+    ),
+    ("mdbook-backmatter", &sections[main_end..], 0),
+  ];
+
+  for (matter, items, level_offset) in matters {
+    if items.is_empty() {
+      continue;
+    }
+
+    writeln!(typst_str, "#{}[", matter)?;
+
+    for item in items {
+      let (item_str, item_map) = convert_book_item(ctx, cfg, &links, cache, item, level_offset)?;
+
+      source_map.append(item_map, typst_str.len());
+
+      writeln!(typst_str, "{}", item_str)?;
+    }
+
+    writeln!(typst_str, "]")?;
   }
 
-  let placeholder = "/**** MDBOOK_TYPST_PDF_PLACEHOLDER ****/\n";
-  let target = output_template.find(placeholder).unwrap_or_default() + placeholder.len();
+  let (marker, prefix, suffix) = match template.version {
+    TemplateVersion::V1 => (PLACEHOLDER, format!("{}\n", PLACEHOLDER), ""),
+    TemplateVersion::V2 => (BODY_MARKER, "#let body = [\n".to_string(), "]"),
+  };
+
+  // The template was checked to contain the marker exactly once.
+  let target = output_template.find(marker).unwrap_or_default();
+
+  output_template.replace_range(
+    target..target + marker.len(),
+    &format!("{}{}{}", prefix, typst_str, suffix),
+  );
+
+  // Set the metadata first so that the template can still override it.
+  let metadata = document_metadata(ctx, cfg, title);
 
-  output_template.insert_str(target, &typst_str);
+  output_template.insert_str(0, &metadata);
 
-  Ok(output_template)
+  source_map.shift(metadata.len() + target + prefix.len());
+
+  Ok((output_template, source_map))
 }
 
 fn convert_book_item(
   ctx: &RenderContext,
   cfg: &Config,
+  links: &LinkResolver,
+  cache: &mut ChapterCache,
   item: &BookItem,
-) -> Result<String, anyhow::Error> {
+  level_offset: usize,
+) -> Result<(String, SourceMap), anyhow::Error> {
   let mut book_item_str = String::new();
 
-  if let BookItem::Chapter(ref ch) = *item {
-    let label_path = ch
-      .source_path
-      .to_owned()
-      .ok_or(anyhow!("source_path not found"))?;
-
-    let label = label_path
-      .as_path()
-      .file_name()
-      .and_then(|f| f.to_str())
-      .and_then(|f| f.split('.').next())
-      .ok_or(anyhow!("label not found"))?;
-
-    let invisible_heading = if let Some(number) = &ch.number {
-      if cfg.section_number {
-        format!(
-          "#{{\n  show heading: none\n  set text(size: 0pt, fill: white)\n  heading(numbering: none, level: {}, outlined: true)[#\"{} {}\"]\n}} <{}.html>",
-          number.len(),
-          number,
-          ch.name,
-          label,
-        )
+  let mut source_map = SourceMap::default();
+
+  match item {
+    BookItem::Chapter(ch) => {
+      let level = ch.number.as_ref().map_or(1, |number| number.len()) + level_offset;
+
+      let title = match &ch.number {
+        Some(number) if cfg.section_number => {
+          format!("#{}", typst_string(&format!("{} {}", number, ch.name)))
+        }
+        _ => ch.name.clone(),
+      };
+
+      if let Some(label_path) = &ch.source_path {
+        let label = links.label(label_path).ok_or(anyhow!("label not found"))?;
+
+        let heading = invisible_heading(level, &title, Some(&chapter_anchor(label)));
+
+        let key = hash128(&(label_path, &ch.content, &heading));
+
+        let (content_str, content_map) = cache.get_or_convert(key, |images| {
+          convert_content(
+            ctx,
+            cfg,
+            links,
+            label_path,
+            &ch.content,
+            label,
+            &heading,
+            images,
+          )
+        })?;
+
+        source_map.append(content_map, book_item_str.len());
+
+        writeln!(book_item_str, "{}", content_str)?;
+      } else if cfg.draft_placeholder {
+        writeln!(book_item_str, "{}", invisible_heading(level, &title, None))?;
+        writeln!(book_item_str, "#mdbook-draft({})", typst_string(&ch.name))?;
       } else {
-        format!(
-          "#{{\n  show heading: none\n  set text(size: 0pt, fill: white)\n  heading(numbering: none, level: {}, outlined: true)[{}]\n}} <{}.html>",
-          number.len(),
-          ch.name,
-          label
-        )
+        // Draft chapters have no content to render.
+        tracing::info!("skipping draft chapter `{}`", ch.name);
       }
-    } else {
-      format!(
-        "#{{\n  show heading: none\n  set text(size: 0pt, fill: white)\n  heading(numbering: none, level: 1, outlined: true)[{}]\n}} <{}.html>",
-        ch.name, label,
-      )
+
+      if (ch.source_path.is_some() || cfg.draft_placeholder) && !cfg.chapter_no_pagebreak {
+        writeln!(book_item_str, "#pagebreak(weak: true)")?;
+      }
+
+      for sub_item in &ch.sub_items {
+        let (sub_item_str, sub_item_map) =
+          convert_book_item(ctx, cfg, links, cache, sub_item, level_offset)?;
+
+        source_map.append(sub_item_map, book_item_str.len());
+
+        writeln!(book_item_str, "{}", sub_item_str)?;
+      }
+    }
+    BookItem::PartTitle(title) => {
+      writeln!(book_item_str, "#mdbook-part({})", typst_string(title))?;
+    }
+    // Separators only structure mdBook's sidebar.
+    BookItem::Separator => (),
+  }
+
+  Ok((book_item_str, source_map))
+}
+
+/// A heading that only shows up in the outline and carries the chapter label.
+fn invisible_heading(level: usize, title: &str, label: Option<&str>) -> String {
+  format!(
+    "#{{\n  show heading: none\n  set text(size: 0pt, fill: white)\n  heading(numbering: none, level: {}, outlined: true)[{}]\n}}{}",
+    level,
+    title,
+    label.map(|label| format!(" <{}>", label)).unwrap_or_default()
+  )
+}
+
+/// The `set document` rule carrying the book's metadata into the PDF.
+fn document_metadata(ctx: &RenderContext, cfg: &Config, title: &str) -> String {
+  let mut args = vec![format!("title: {}", typst_string(title))];
+
+  if !ctx.config.book.authors.is_empty() {
+    args.push(format!("author: {}", typst_array(&ctx.config.book.authors)));
+  }
+
+  if !cfg.keywords.is_empty() {
+    args.push(format!("keywords: {}", typst_array(&cfg.keywords)));
+  }
+
+  format!("#set document({})\n\n", args.join(", "))
+}
+
+/// Quote strings as a Typst array of string literals.
+fn typst_array(items: &[String]) -> String {
+  let items: String = items
+    .iter()
+    .map(|item| format!("{},", typst_string(item)))
+    .collect();
+
+  format!("({})", items)
+}
+
+/// Quote text as a Typst string literal.
+pub fn typst_string(text: &str) -> String {
+  format!("\"{}\"", text.replace('\\', r#"\\"#).replace('"', r#"\""#))
+}
+
+/// The arguments of an `image` call for the image at `path` in the output.
+/// Images without alt text get the file name they are referred to by as alt
+/// text if the PDF standard requires every image to have one.
+fn image_args(cfg: &Config, path: &str, src: &str, alt: &str) -> String {
+  let alt = if alt.is_empty() && cfg.pdf_standard.is_some_and(PdfStandard::requires_alt_text) {
+    Path::new(src)
+      .file_stem()
+      .map_or(Cow::Borrowed(src), |stem| stem.to_string_lossy())
+  } else {
+    Cow::Borrowed(alt)
+  };
+
+  if alt.is_empty() {
+    typst_string(path)
+  } else {
+    format!("{}, alt: {}", typst_string(path), typst_string(&alt))
+  }
+}
+
+/// An image from Markdown or an `<img>`.
+#[derive(Debug, Default)]
+struct Image<'a> {
+  /// The image as the chapter refers to it.
+  src: &'a str,
+  /// The image relative to the output directory, if it could be resolved.
+  path: Option<&'a str>,
+  alt: &'a str,
+  title: &'a str,
+  width: Option<String>,
+  height: Option<String>,
+  /// Whether the image is part of a paragraph's text rather than standing on
+  /// its own.
+  inline: bool,
+}
+
+/// An image as Typst: a figure if it stands on its own, otherwise a box in
+/// the text. Images that couldn't be resolved are shown as a placeholder.
+fn image_typst(cfg: &Config, image: &Image) -> String {
+  let body = match image.path {
+    Some(path) => {
+      let mut args = image_args(cfg, path, image.src, image.alt);
+
+      if let Some(width) = &image.width {
+        args.push_str(&format!(", width: {}", width));
+      }
+      if let Some(height) = &image.height {
+        args.push_str(&format!(", height: {}", height));
+      }
+
+      format!("image({})", args)
+    }
+    None => format!(
+      "rect(width: {}, inset: 1em, stroke: (paint: luma(160), dash: \"dashed\"), text(fill: luma(120))[Image not available: {}])",
+      if image.inline { "auto" } else { "100%" },
+      escape_text(image.src)
+    ),
+  };
+
+  if image.inline {
+    return format!("#box({});", body);
+  }
+
+  let caption = match cfg.image_caption {
+    ImageCaption::Title => image.title,
+    ImageCaption::Alt => image.alt,
+    ImageCaption::None => "",
+  };
+
+  let mut figure = format!("#figure(\n  {}", body);
+
+  if !caption.is_empty() {
+    figure.push_str(&format!(",\n  caption: [{}]", escape_text(caption)));
+  }
+
+  if cfg.figure_no_numbering {
+    figure.push_str(",\n  numbering: none");
+  }
+
+  figure.push_str("\n)\n");
+
+  figure
+}
+
+/// Escape text for Typst markup.
+pub fn escape_text(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+
+  for ch in text.chars() {
+    match ch {
+      '#' | '$' | '`' | '*' | '_' | '<' | '>' | '@' | '\\' | '[' | ']' => {
+        escaped.push('\\');
+        escaped.push(ch);
+      }
+      _ => escaped.push(ch),
+    }
+  }
+
+  escaped
+}
+
+/// The opening of a `#link` to `dest_url` as written in the chapter at
+/// `source_path`, up to and including the `[`. Links that can't be resolved
+/// are warned about and left out.
+fn link_open(
+  links: &LinkResolver,
+  source_path: &Path,
+  dest_url: &str,
+  warn: impl Fn(&str),
+) -> String {
+  let email_regex: &Regex = EMAIL_REGEX
+    .get_or_init(|| Regex::new(r"(?i)^\w+([\.-]?\w+)*@\w+([\.-]?\w+)*(.\w{2,3})+$").unwrap());
+
+  if dest_url.starts_with("http://")
+    || dest_url.starts_with("https://")
+    || dest_url.starts_with("mailto:")
+  {
+    format!("#link({})[", typst_string(dest_url))
+  } else if email_regex.is_match(dest_url) {
+    format!("#link({})[", typst_string(&format!("mailto:{}", dest_url)))
+  } else {
+    match links.resolve(source_path, dest_url) {
+      Ok(target) => format!("#link(<{}>)[", target),
+      Err(err) => {
+        warn(&err.to_string());
+
+        "#[".to_string()
+      }
+    }
+  }
+}
+
+/// The chapter raw HTML is converted in.
+struct HtmlChapter<'a, W: Fn(&str)> {
+  ctx: &'a RenderContext,
+  cfg: &'a Config,
+  links: &'a LinkResolver,
+  source_path: &'a Path,
+  images: &'a ImageFiles,
+  /// Whether the HTML is part of a paragraph's text.
+  inline: bool,
+  warn: W,
+}
+
+impl<W: Fn(&str)> HtmlContext for HtmlChapter<'_, W> {
+  fn link(&self, href: &str) -> String {
+    link_open(self.links, self.source_path, href, &self.warn)
+  }
+
+  fn image(&self, tag: &HtmlTag, inline: bool) -> Result<String, anyhow::Error> {
+    let Some(src) = tag.attr("src") else {
+      (self.warn)("`<img>` without `src` is left out");
+
+      return Ok(String::new());
     };
 
-    if cfg.chapter_no_pagebreak {
-      writeln!(
-        book_item_str,
-        "{}",
-        convert_content(ctx, &ch.content, label, &invisible_heading)?
-      )?;
-    } else {
-      writeln!(
-        book_item_str,
-        "{}#pagebreak(weak: true)",
-        convert_content(ctx, &ch.content, label, &invisible_heading)?
-      )?;
+    let path = images::resolve(
+      self.ctx,
+      self.cfg,
+      self.source_path,
+      src,
+      self.images,
+      &self.warn,
+    )?;
+
+    let (width, height) = html::image_size(tag);
+
+    Ok(image_typst(
+      self.cfg,
+      &Image {
+        src,
+        path: path.as_deref(),
+        alt: tag.attr("alt").unwrap_or_default(),
+        title: tag.attr("title").unwrap_or_default(),
+        width,
+        height,
+        inline: inline || self.inline,
+      },
+    ))
+  }
+
+  fn markdown(&self, text: &str) -> String {
+    let mut typst = String::new();
+
+    for event in Parser::new_ext(text, parser_options()) {
+      match event {
+        Event::Start(Tag::Emphasis) => typst.push_str("#emph["),
+        Event::Start(Tag::Strong) => typst.push_str("#strong["),
+        Event::Start(Tag::Strikethrough) => typst.push_str("#strike["),
+        Event::Start(Tag::Link { dest_url, .. }) => typst.push_str(&self.link(&dest_url)),
+        Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link) => {
+          typst.push_str("];")
+        }
+        Event::Text(t) => typst.push_str(&escape_text(&t)),
+        Event::Code(t) => typst.push_str(&format!("#raw({});", typst_string(&t))),
+        Event::InlineMath(t) => typst.push_str(&format!("${}$", convert_math(&t, &self.warn))),
+        Event::SoftBreak => typst.push(' '),
+        Event::HardBreak => typst.push_str("#linebreak();"),
+        _ => (),
+      }
     }
+
+    typst
   }
 
-  Ok(book_item_str)
+  fn warn(&self, message: &str) {
+    (self.warn)(message)
+  }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn convert_content(
   ctx: &RenderContext,
+  cfg: &Config,
+  links: &LinkResolver,
+  source_path: &Path,
   content: &str,
   label: &str,
   invisible_heading: &str,
-) -> Result<String, anyhow::Error> {
+  images: &ImageFiles,
+) -> Result<(String, SourceMap), anyhow::Error> {
   let mut content_str = String::new();
 
   let mut heading = String::new();
 
   let mut writen_invisible_heading = false;
 
-  let options = Options::ENABLE_SMART_PUNCTUATION
-    | Options::ENABLE_STRIKETHROUGH
-    | Options::ENABLE_FOOTNOTES
-    | Options::ENABLE_TASKLISTS
-    | Options::ENABLE_TABLES;
+  let options = parser_options();
+
+  let mathjax_support = ctx
+    .config
+    .html_config()
+    .map(|html| html.mathjax_support)
+    .unwrap_or_default();
+
+  let rewritten = if mathjax_support {
+    Some(math::rewrite_mathjax_delimiters(content))
+  } else {
+    None
+  };
+
+  // Parser offsets refer to the rewritten content, and are mapped back to the
+  // chapter file before they are reported.
+  let original = |offset: usize| {
+    rewritten
+      .as_ref()
+      .map_or(offset, |rewritten| rewritten.original(offset))
+  };
+
+  let parsed = rewritten
+    .as_ref()
+    .map_or(content, |rewritten| &rewritten.content);
+
+  let parser = Parser::new_ext(parsed, options).into_offset_iter();
+
+  let markdown_path = ctx.config.book.src.join(source_path);
+
+  let warn = |offset: usize, message: &str| {
+    diagnostic::conversion_warning(
+      cfg.diagnostic_format.unwrap_or_default(),
+      &markdown_path,
+      line_number(content, original(offset)),
+      message,
+    )
+  };
+
+  let mut source_map = SourceMap::chapter(markdown_path.clone(), content.to_string());
+
+  // Footnote definitions may appear anywhere in the chapter, so they are taken
+  // out of the event stream up front and replayed at their first reference.
+  let mut events = VecDeque::new();
+  let mut footnotes: HashMap<String, SourceEvents> = HashMap::new();
+  let mut footnote_definition: Option<(String, SourceEvents)> = None;
+
+  for (event, range) in parser {
+    match event {
+      Event::Start(Tag::FootnoteDefinition(ref name)) => {
+        footnote_definition = Some((name.to_string(), vec![(event, range)]));
+      }
+      Event::End(TagEnd::FootnoteDefinition) => {
+        if let Some((name, mut definition)) = footnote_definition.take() {
+          definition.push((event, range));
+          footnotes.entry(name).or_insert(definition);
+        }
+      }
+      _ => match footnote_definition {
+        Some((_, ref mut definition)) => definition.push((event, range)),
+        None => events.push_back((event, range)),
+      },
+    }
+  }
 
-  let parser = Parser::new_ext(content, options);
+  let mut footnote_numbers: HashMap<String, usize> = HashMap::new();
+
+  let mut writen_endnotes = false;
 
   let mut event_stack = Vec::new();
 
-  for event in parser {
+  let mut paragraph_start = None;
+
+  let mut code_block_badge = None;
+
+  let mut html = HtmlConverter::default();
+  let mut html_table_start: Option<usize> = None;
+
+  let hidelines = ctx
+    .config
+    .html_config()
+    .map(|html| html.code.hidelines)
+    .unwrap_or_default();
+
+  let attr_regex: &Regex =
+    ADMONISH_ATTR_REGEX.get_or_init(|| Regex::new(r#"(\w+)=(?:"([^"]*)"|(\S*))"#).unwrap());
+
+  while let Some((event, range)) = events.pop_front() {
+    source_map.map(content_str.len(), original(range.start));
+
     match event {
       Event::Start(Tag::Heading { level, .. }) => {
         event_stack.push(EventType::Heading);
@@ -157,9 +666,8 @@ fn convert_content(
 
         writeln!(
           content_str,
-          "] <{}.html-{}>",
-          label,
-          mdbook::utils::normalize_id(&heading)
+          "] <{}>",
+          heading_anchor(label, &mdbook::utils::normalize_id(&heading))
         )?;
 
         if !writen_invisible_heading {
@@ -172,7 +680,20 @@ fn convert_content(
       Event::End(TagEnd::Emphasis) => write!(content_str, "_")?,
       Event::Start(Tag::Strong) => write!(content_str, "*")?,
       Event::End(TagEnd::Strong) => write!(content_str, "*")?,
-      Event::Start(Tag::BlockQuote(_)) => write!(content_str, "#quote(block: true)[")?,
+      Event::Start(Tag::Strikethrough) => write!(content_str, "#strike[")?,
+      Event::End(TagEnd::Strikethrough) => write!(content_str, "]")?,
+      Event::Start(Tag::BlockQuote(None)) => write!(content_str, "#quote(block: true)[")?,
+      Event::Start(Tag::BlockQuote(Some(kind))) => {
+        let kind = match kind {
+          BlockQuoteKind::Note => "note",
+          BlockQuoteKind::Tip => "tip",
+          BlockQuoteKind::Important => "important",
+          BlockQuoteKind::Warning => "warning",
+          BlockQuoteKind::Caution => "caution",
+        };
+
+        write!(content_str, "#mdbook-callout(kind: \"{}\")[", kind)?
+      }
       Event::End(TagEnd::BlockQuote(_)) => writeln!(content_str, "]")?,
       Event::Start(Tag::List(None)) => {
         event_stack.push(EventType::List);
@@ -189,26 +710,18 @@ fn convert_content(
         _ => write!(content_str, "- ")?,
       },
       Event::End(TagEnd::Item) => writeln!(content_str)?,
-      Event::Start(Tag::Paragraph) => (),
-      Event::End(TagEnd::Paragraph) => write!(content_str, "\n\n")?,
+      Event::Start(Tag::Paragraph) => paragraph_start = Some(content_str.len()),
+      Event::End(TagEnd::Paragraph) => {
+        paragraph_start = None;
+
+        write!(content_str, "\n\n")?
+      }
       Event::Start(Tag::Link { dest_url, .. }) => {
-        let email_regex: &Regex = EMAIL_REGEX
-          .get_or_init(|| Regex::new(r"(?i)^\w+([\.-]?\w+)*@\w+([\.-]?\w+)*(.\w{2,3})+$").unwrap());
-
-        if dest_url.starts_with("http://") || dest_url.starts_with("https://") {
-          write!(content_str, "#link(\"{}\")[", dest_url)?
-        } else if email_regex.is_match(&dest_url) {
-          write!(content_str, "#link(\"mailto:{}\")[", dest_url)?
-        } else if dest_url.starts_with('#') {
-          write!(
-            content_str,
-            "#link(<{}.html{}>)[",
-            label,
-            dest_url.replace('#', "-")
-          )?
-        } else {
-          write!(content_str, "#link(<{}>)[", dest_url.replace('#', "-"))?
-        }
+        let link = link_open(links, source_path, &dest_url, |message| {
+          warn(range.start, message)
+        });
+
+        write!(content_str, "{}", link)?
       }
       Event::End(TagEnd::Link) => write!(content_str, "]")?,
       Event::Start(Tag::Table(align)) => {
@@ -241,36 +754,108 @@ fn convert_content(
       Event::End(TagEnd::TableRow) => (),
       Event::Start(Tag::TableCell) => write!(content_str, "[")?,
       Event::End(TagEnd::TableCell) => writeln!(content_str, "],")?,
-      Event::Start(Tag::Image { dest_url, .. }) => {
-        event_stack.push(EventType::Image);
-
-        let src_path = ctx
-          .root
-          .join(
-            ctx
-              .config
-              .book
-              .src
-              .to_str()
-              .ok_or(anyhow!("src not found"))?,
-          )
-          .join(dest_url.to_string());
-        let dest_path = ctx.destination.join(dest_url.to_string());
-
-        let dest_dir = dest_path.parent().ok_or(anyhow!("destination not found"))?;
+      Event::Start(Tag::Image {
+        dest_url, title, ..
+      }) => {
+        let path = images::resolve(ctx, cfg, source_path, &dest_url, images, |message| {
+          warn(range.start, message)
+        })?;
+
+        // An image is a figure if it is all there is in its paragraph.
+        let mut depth = 0;
+        let after_image = events.iter().position(|(event, _)| match event {
+          Event::Start(Tag::Image { .. }) => {
+            depth += 1;
+            false
+          }
+          Event::End(TagEnd::Image) if depth == 0 => true,
+          Event::End(TagEnd::Image) => {
+            depth -= 1;
+            false
+          }
+          _ => false,
+        });
+        let standalone = paragraph_start == Some(content_str.len())
+          && after_image.is_some_and(|end| {
+            matches!(
+              events.get(end + 1),
+              Some((Event::End(TagEnd::Paragraph), _))
+            )
+          });
+
+        // The alt text is plain text, markup inside `![...]` is left out.
+        let mut alt = String::new();
+
+        for (event, _) in events.drain(..after_image.map_or(0, |end| end + 1)) {
+          match event {
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) | Event::DisplayMath(t) => {
+              alt.push_str(&t)
+            }
+            Event::SoftBreak | Event::HardBreak => alt.push(' '),
+            _ => (),
+          }
+        }
 
-        fs::create_dir_all(dest_dir)?;
+        let image = Image {
+          src: &dest_url,
+          path: path.as_deref(),
+          alt: &alt,
+          title: &title,
+          inline: !standalone,
+          ..Default::default()
+        };
 
-        if !dest_path.exists() {
-          fs::copy(src_path, dest_path)?;
+        write!(content_str, "{}", image_typst(cfg, &image))?
+      }
+      Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info)))
+        if info.split_whitespace().next() == Some("admonish") =>
+      {
+        let mut body = String::new();
+
+        while let Some((event, _)) = events.pop_front() {
+          match event {
+            Event::Text(t) => body.push_str(&t),
+            Event::End(TagEnd::CodeBlock) => break,
+            _ => (),
+          }
         }
 
-        write!(content_str, "#figure(\n  image(\"{}\")\n)", dest_url)?
-      }
-      Event::End(TagEnd::Image) => {
-        event_stack.pop();
+        let directive = info
+          .split_whitespace()
+          .skip(1)
+          .find(|word| !word.contains('='))
+          .unwrap_or("note");
+
+        let title = attr_regex
+          .captures_iter(info)
+          .find(|caps| &caps[1] == "title")
+          .and_then(|caps| caps.get(2).or_else(|| caps.get(3)))
+          .map(|title| title.as_str());
+
+        let title_arg = match title {
+          Some("") => ", title: none".to_string(),
+          Some(title) => format!(", title: {}", typst_string(title)),
+          None => "".to_string(),
+        };
 
-        writeln!(content_str)?
+        writeln!(
+          content_str,
+          "#mdbook-callout(kind: \"{}\"{})[",
+          admonish_kind(directive),
+          title_arg
+        )?;
+
+        // The body is Markdown itself, so feed it back through the converter
+        // and close the callout like a block quote.
+        events.push_front((Event::End(TagEnd::BlockQuote(None)), range.clone()));
+
+        let body_events: SourceEvents = Parser::new_ext(&body, options)
+          .map(|event| (event.into_static(), range.clone()))
+          .collect();
+
+        for body_event in body_events.into_iter().rev() {
+          events.push_front(body_event);
+        }
       }
       Event::Start(Tag::CodeBlock(ref lang)) => match lang {
         CodeBlockKind::Indented => {
@@ -278,78 +863,70 @@ fn convert_content(
 
           writeln!(content_str, "````")?
         }
-        CodeBlockKind::Fenced(lang) => {
-          event_stack.push(EventType::CodeBlockFenced(lang.to_string()));
+        CodeBlockKind::Fenced(info) => {
+          event_stack.push(EventType::CodeBlockFenced(info.to_string()));
 
-          let langs: Vec<&str> = lang.split(',').collect();
+          let info = code::parse_info(info);
+          let lang = info.lang.as_deref().unwrap_or_default();
 
-          if !langs.is_empty() {
-            let mut ferris_prefix = "".to_string();
+          code_block_badge = code_badge(ctx, cfg, &info, images, |message| {
+            warn(range.start, message)
+          })?;
 
-            for l in langs.iter().skip(1) {
-              match l {
-                &"does_not_compile" | &"not_desired_behavior" | &"panics" => {
-                  ferris_prefix = "#columns(1)[\n".to_string();
-                }
-                _ => (),
-              }
-            }
+          if code_block_badge.is_some() {
+            writeln!(content_str, "#columns(1)[")?;
+          }
 
-            writeln!(content_str, "{}````{}", ferris_prefix, langs[0])?
-          } else {
-            writeln!(content_str, "````")?
+          // The code is written right away to leave out or mark the lines
+          // mdBook hides.
+          let code: String = events
+            .iter()
+            .map_while(|(event, _)| match event {
+              Event::Text(t) => Some(t.as_ref()),
+              _ => None,
+            })
+            .collect();
+
+          let prefix = info
+            .hidelines()
+            .or_else(|| hidelines.get(lang).map(String::as_str));
+
+          let lines = code::code_lines(&code, lang, prefix);
+
+          let hidden_lines: Vec<usize> = match cfg.hidden_lines {
+            HiddenLines::Dim => lines
+              .iter()
+              .enumerate()
+              .filter(|(_, line)| line.hidden)
+              .map(|(i, _)| i + 1)
+              .collect(),
+            HiddenLines::Hide | HiddenLines::Show => vec![],
+          };
+
+          writeln!(
+            content_str,
+            "#mdbook-code({})[\n````{}",
+            info.typst_args(&hidden_lines),
+            lang
+          )?;
+
+          for line in &lines {
+            if !line.hidden || cfg.hidden_lines != HiddenLines::Hide {
+              writeln!(content_str, "{}", line.text)?;
+            }
           }
         }
       },
       Event::End(TagEnd::CodeBlock) => {
         match event_stack.last() {
           Some(EventType::CodeBlockIndented) => writeln!(content_str, "````")?,
-          Some(EventType::CodeBlockFenced(lang)) => {
-            let langs: Vec<&str> = lang.split(',').collect();
-
-            if !langs.is_empty() {
-              let mut ferris_suffix = "".to_string();
-
-              for l in langs.iter().skip(1) {
-                match l {
-                  &"does_not_compile" | &"not_desired_behavior" | &"panics" => {
-                    let ferris_src_path = format!("img/ferris/{}.svg", l);
-
-                    let src_path = ctx
-                      .root
-                      .join(
-                        ctx
-                          .config
-                          .book
-                          .src
-                          .to_str()
-                          .ok_or(anyhow!("src not found"))?,
-                      )
-                      .join(&ferris_src_path);
-
-                    let dest_path = ctx.destination.join(&ferris_src_path);
-
-                    let dest_dir = dest_path.parent().ok_or(anyhow!("destination not found"))?;
-
-                    fs::create_dir_all(dest_dir)?;
-
-                    if !dest_path.exists() {
-                      fs::copy(src_path, dest_path)?;
-                    }
-
-                    ferris_suffix = format!(
-                      "\n#place(\n  top + right,\n  figure(\n    image(\"{}\", width: 10%)\n  )\n)\n]",
-                      ferris_src_path
-                    );
-                  }
-                  _ => (),
-                }
-              }
-
-              writeln!(content_str, "````{}", ferris_suffix)?
-            } else {
-              writeln!(content_str, "````")?
-            }
+          Some(EventType::CodeBlockFenced(_)) => {
+            let badge_suffix = match code_block_badge.take() {
+              Some(badge) => format!("\n#place(\n  top + right,\n  {}\n)\n]", badge),
+              None => "".to_string(),
+            };
+
+            writeln!(content_str, "````\n]{}", badge_suffix)?
           }
           _ => writeln!(content_str, "````")?,
         }
@@ -367,75 +944,41 @@ fn convert_content(
           t.replace('\\', r#"\\"#).replace('"', r#"\""#)
         )?;
       }
-      Event::Html(t) | Event::InlineHtml(t) => {
-        match t.trim() {
-          "<sup>" => {
-            write!(content_str, "#super[")?;
+      Event::Html(ref t) | Event::InlineHtml(ref t) => {
+        // Markdown between the HTML of a table belongs to its cells.
+        if let Some(start) = html_table_start.take() {
+          html.capture(&content_str.split_off(start.min(content_str.len())));
+        }
 
-            continue;
-          }
-          "</sup>" => {
-            write!(content_str, "]")?;
+        let chapter = HtmlChapter {
+          ctx,
+          cfg,
+          links,
+          source_path,
+          images,
+          inline: matches!(event, Event::InlineHtml(_)),
+          warn: |message: &str| warn(range.start, message),
+        };
 
-            continue;
-          }
-          _ => (),
-        }
+        write!(content_str, "{}", html.convert(t, &chapter)?)?;
 
-        let dom = parse_document(RcDom::default(), Default::default())
-          .from_utf8()
-          .read_from(&mut t.as_bytes())?;
-
-        let dom_children = &dom.document.children.borrow();
-
-        if dom_children.len() > 0 && matches!(dom_children[0].data, NodeData::Element { .. }) {
-          let html_children = &dom_children[0].children.borrow();
-
-          if html_children.len() > 1 {
-            let body_children = &html_children[1].children.borrow();
-
-            if body_children.len() > 0 {
-              if let NodeData::Element { name, attrs, .. } = &body_children[0].data {
-                match name.local.as_ref() {
-                  "img" => {
-                    for attr in attrs.borrow().iter() {
-                      if attr.name.local.as_ref() == "src" {
-                        let attr_src_path = attr.value.to_string();
-
-                        let src_path = ctx
-                          .root
-                          .join(
-                            ctx
-                              .config
-                              .book
-                              .src
-                              .to_str()
-                              .ok_or(anyhow!("src not found"))?,
-                          )
-                          .join(&attr_src_path);
-                        let dest_path = ctx.destination.join(&attr_src_path);
-
-                        let dest_dir =
-                          dest_path.parent().ok_or(anyhow!("destination not found"))?;
-
-                        fs::create_dir_all(dest_dir)?;
-
-                        if !dest_path.exists() {
-                          fs::copy(src_path, dest_path)?;
-                        }
-
-                        writeln!(content_str, "#figure(\n  image(\"{}\")\n)", attr_src_path)?
-                      }
-                    }
-                  }
-                  "span" => (),
-                  _ => (),
-                }
-              }
-            }
-          }
+        if html.in_table() {
+          html_table_start = Some(content_str.len());
         }
       }
+      Event::End(TagEnd::HtmlBlock) => {
+        let chapter = HtmlChapter {
+          ctx,
+          cfg,
+          links,
+          source_path,
+          images,
+          inline: false,
+          warn: |message: &str| warn(range.start, message),
+        };
+
+        write!(content_str, "{}", html.flush(&chapter))?
+      }
       Event::Text(t) => {
         if event_stack.contains(&EventType::Heading) {
           heading.push_str(&t);
@@ -443,29 +986,437 @@ fn convert_content(
 
         match event_stack.last() {
           Some(EventType::CodeBlockIndented) => write!(content_str, "{}", t)?,
-          Some(EventType::CodeBlockFenced(_)) => write!(content_str, "{}", t)?,
+          // Written with the start of the code block.
+          Some(EventType::CodeBlockFenced(_)) => (),
           Some(EventType::TableHead) => write!(content_str, "*{}*", t)?,
-          Some(EventType::Image) => write!(content_str, "/* {} */", t)?,
-          _ => {
-            let mut transformed_text = String::with_capacity(t.len());
-            for ch in t.chars() {
-              match ch {
-                '#' | '$' | '`' | '*' | '_' | '<' | '>' | '@' => {
-                  transformed_text.push('\\');
-                  transformed_text.push(ch);
-                }
-                _ => transformed_text.push(ch),
-              }
-            }
+          _ => write!(content_str, "{}", escape_text(&t))?,
+        }
+      }
+      Event::SoftBreak => writeln!(content_str)?,
+      Event::InlineMath(t) => write!(
+        content_str,
+        "${}$",
+        convert_math(&t, |message| warn(range.start, message))
+      )?,
+      Event::DisplayMath(t) => write!(
+        content_str,
+        "$ {} $",
+        convert_math(&t, |message| warn(range.start, message))
+      )?,
+      Event::HardBreak => writeln!(content_str, "#linebreak()")?,
+      Event::Rule => write!(content_str, "#line(length: 100%)\n\n")?,
+      Event::TaskListMarker(checked) => {
+        if checked {
+          write!(content_str, "☑ ")?
+        } else {
+          write!(content_str, "☐ ")?
+        }
+      }
+      Event::FootnoteReference(name) => {
+        let footnote_label = footnote_label(label, &name);
 
-            write!(content_str, "{}", transformed_text)?
+        if let Some(number) = footnote_numbers.get(name.as_ref()) {
+          if cfg.chapter_endnotes {
+            write!(content_str, "#super[{}]", number)?;
+          } else {
+            write!(content_str, "#footnote(<{}>)", footnote_label)?;
+          }
+
+          continue;
+        }
+
+        let Some(definition) = footnotes.get(name.as_ref()) else {
+          warn(range.start, &format!("footnote `{}` is not defined", name));
+
+          write!(content_str, "\\[^{}\\]", name)?;
+
+          continue;
+        };
+
+        let number = footnote_numbers.len() + 1;
+
+        footnote_numbers.insert(name.to_string(), number);
+
+        if cfg.chapter_endnotes {
+          write!(content_str, "#super[{}]", number)?;
+
+          events.extend(definition.iter().cloned());
+        } else {
+          for footnote_event in definition.iter().rev() {
+            events.push_front(footnote_event.clone());
           }
         }
       }
-      Event::SoftBreak => writeln!(content_str)?,
+      Event::Start(Tag::FootnoteDefinition(name)) => {
+        let number = footnote_numbers
+          .get(name.as_ref())
+          .copied()
+          .unwrap_or_default();
+
+        event_stack.push(EventType::Footnote(name.to_string()));
+
+        if cfg.chapter_endnotes {
+          if !writen_endnotes {
+            writeln!(content_str, "\n#line(length: 30%)")?;
+
+            writen_endnotes = true;
+          }
+
+          write!(content_str, "#enum.item({})[", number)?;
+        } else {
+          write!(content_str, "#footnote[")?;
+        }
+      }
+      Event::End(TagEnd::FootnoteDefinition) => {
+        let footnote_label = match event_stack.pop() {
+          Some(EventType::Footnote(name)) => footnote_label(label, &name),
+          _ => return Err(anyhow!("unbalanced footnote definition in `{}`", label)),
+        };
+
+        content_str.truncate(content_str.trim_end().len());
+
+        if cfg.chapter_endnotes {
+          writeln!(content_str, "]")?;
+        } else {
+          write!(content_str, "] <{}>", footnote_label)?;
+        }
+      }
       _ => (),
     }
   }
 
-  Ok(content_str)
+  let chapter = HtmlChapter {
+    ctx,
+    cfg,
+    links,
+    source_path,
+    images,
+    inline: false,
+    warn: |message: &str| warn(parsed.len(), message),
+  };
+
+  if let Some(start) = html_table_start {
+    html.capture(&content_str.split_off(start.min(content_str.len())));
+  }
+
+  content_str.push_str(&html.finish(&chapter));
+
+  source_map.unmap(content_str.len());
+
+  if !writen_invisible_heading {
+    let heading = format!("{}\n", invisible_heading);
+
+    content_str.insert_str(0, &heading);
+
+    source_map.shift(heading.len());
+  }
+
+  Ok((content_str, source_map))
+}
+
+/// The options chapters are parsed with.
+pub fn parser_options() -> Options {
+  Options::ENABLE_SMART_PUNCTUATION
+    | Options::ENABLE_STRIKETHROUGH
+    | Options::ENABLE_FOOTNOTES
+    | Options::ENABLE_TASKLISTS
+    | Options::ENABLE_TABLES
+    | Options::ENABLE_MATH
+    | Options::ENABLE_GFM
+}
+
+/// The label of a chapter, derived from its full source path so chapters with
+/// the same file name in different directories stay distinct.
+///
+/// `part1/intro.md` becomes `part1:intro`. Characters Typst does not accept
+/// in labels are replaced by `_`, [`LinkResolver`] tells apart chapters that
+/// end up with the same label.
+pub fn chapter_label(source_path: &Path) -> Option<String> {
+  let parts: Vec<String> = source_path
+    .with_extension("")
+    .components()
+    .filter_map(|component| match component {
+      Component::Normal(part) => Some(
+        part
+          .to_string_lossy()
+          .chars()
+          .map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' {
+              c
+            } else {
+              '_'
+            }
+          })
+          .collect(),
+      ),
+      _ => None,
+    })
+    .collect();
+
+  if parts.is_empty() {
+    None
+  } else {
+    Some(parts.join(":"))
+  }
+}
+
+/// The label attached to the (invisible) chapter heading.
+pub fn chapter_anchor(label: &str) -> String {
+  format!("{}.html", label)
+}
+
+/// The label attached to a heading inside a chapter.
+pub fn heading_anchor(label: &str, id: &str) -> String {
+  format!("{}.html-{}", label, id)
+}
+
+fn line_number(content: &str, offset: usize) -> usize {
+  content.as_bytes()[..offset]
+    .iter()
+    .filter(|&&b| b == b'\n')
+    .count()
+    + 1
+}
+
+fn footnote_label(label: &str, name: &str) -> String {
+  format!("{}.html-fn-{}", label, mdbook::utils::normalize_id(name))
+}
+
+fn convert_math(latex: &str, warn: impl Fn(&str)) -> String {
+  let math = math::latex_to_typst(latex);
+
+  if !math.unknown.is_empty() {
+    warn(&format!(
+      "could not translate LaTeX commands: {}",
+      math.unknown.join(", ")
+    ));
+  }
+
+  math.typst
+}
+
+/// The badge of a code block, copying its image into the output. Configured
+/// images that are missing fall back to the bundled Ferris image, if there is
+/// one, with a warning. Without configuration, a book's own
+/// `img/ferris/*.svg` take precedence over the bundled ones. The copy is
+/// recorded in `files`.
+fn code_badge(
+  ctx: &RenderContext,
+  cfg: &Config,
+  info: &CodeInfo,
+  files: &ImageFiles,
+  warn: impl Fn(&str),
+) -> Result<Option<String>, anyhow::Error> {
+  let src_dir = ctx.root.join(&ctx.config.book.src);
+
+  for class in &info.classes {
+    let configured = match cfg.badges.get(class) {
+      Some(Badge::Enabled(false)) => continue,
+      Some(Badge::Text { text }) => {
+        return Ok(Some(format!(
+          "box(inset: 4pt, radius: 4pt, fill: luma(230), text(size: 0.8em, {}))",
+          typst_string(text)
+        )))
+      }
+      Some(Badge::Image { image }) => Some(image),
+      Some(Badge::Enabled(true)) | None => None,
+    };
+
+    let bundled = code::FERRIS
+      .iter()
+      .find(|(name, _)| name == class)
+      .map(|(_, svg)| *svg);
+
+    if configured.is_none() && bundled.is_none() {
+      continue;
+    }
+
+    let image_path = match configured {
+      Some(image) => image.clone(),
+      None => format!("img/ferris/{}.svg", class),
+    };
+
+    let src_path = src_dir.join(&image_path);
+
+    let dest_path = if src_path.is_file() {
+      let dest_path = ctx.destination.join(&image_path);
+
+      files.record_output(&dest_path);
+
+      if !dest_path.exists() {
+        fs::create_dir_all(dest_path.parent().ok_or(anyhow!("destination not found"))?)?;
+        fs::copy(src_path, &dest_path)?;
+      }
+
+      image_path
+    } else {
+      if configured.is_some() {
+        warn(&format!(
+          "badge image `{}` for `{}` code blocks not found",
+          src_path.display(),
+          class
+        ));
+      }
+
+      let Some(svg) = bundled else {
+        continue;
+      };
+
+      let bundled_path = format!("mdbook-typst-pdf/ferris/{}.svg", class);
+      let dest_path = ctx.destination.join(&bundled_path);
+
+      files.record_output(&dest_path);
+
+      if !dest_path.exists() {
+        fs::create_dir_all(dest_path.parent().ok_or(anyhow!("destination not found"))?)?;
+        fs::write(&dest_path, svg)?;
+      }
+
+      bundled_path
+    };
+
+    return Ok(Some(format!(
+      "figure(\n    image({}, width: 10%)\n  )",
+      typst_string(&dest_path)
+    )));
+  }
+
+  Ok(None)
+}
+
+/// Map mdbook-admonish directives and their aliases onto callout kinds.
+fn admonish_kind(directive: &str) -> &'static str {
+  match directive {
+    "abstract" | "summary" | "tldr" => "abstract",
+    "info" | "todo" => "info",
+    "tip" | "hint" | "important" => "tip",
+    "success" | "check" | "done" => "success",
+    "question" | "help" | "faq" => "question",
+    "warning" | "caution" | "attention" => "warning",
+    "failure" | "fail" | "missing" => "failure",
+    "danger" | "error" => "danger",
+    "bug" => "bug",
+    "example" => "example",
+    "quote" | "cite" => "quote",
+    _ => "note",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mdbook::book::Book;
+
+  use super::*;
+
+  fn convert(markdown: &str) -> String {
+    let ctx = RenderContext::new("book", Book::new(), mdbook::Config::default(), "book/out");
+    let links = LinkResolver::new(&ctx);
+
+    let (typst, _) = convert_content(
+      &ctx,
+      &Config::default(),
+      &links,
+      Path::new("chapter.md"),
+      markdown,
+      "chapter",
+      "",
+      &ImageFiles::default(),
+    )
+    .unwrap();
+
+    typst
+  }
+
+  #[test]
+  fn alerts() {
+    let typst = convert("> [!NOTE]\n> Read this.\n");
+
+    assert!(
+      typst.contains("#mdbook-callout(kind: \"note\")[Read this."),
+      "{}",
+      typst
+    );
+
+    let typst = convert("> [!WARNING]\n> Be careful.\n");
+
+    assert!(
+      typst.contains("#mdbook-callout(kind: \"warning\")[Be careful."),
+      "{}",
+      typst
+    );
+    assert!(!typst.contains("!WARNING"), "{}", typst);
+  }
+
+  #[test]
+  fn plain_block_quote() {
+    let typst = convert("> Quoted.\n");
+
+    assert!(typst.contains("#quote(block: true)[Quoted."), "{}", typst);
+  }
+
+  #[test]
+  fn admonish_blocks() {
+    let typst = convert("```admonish tip title=\"Hint\"\nUse *this*.\n```\n");
+
+    assert!(
+      typst.contains("#mdbook-callout(kind: \"tip\", title: \"Hint\")["),
+      "{}",
+      typst
+    );
+    assert!(typst.contains("Use _this_."), "{}", typst);
+  }
+
+  #[test]
+  fn cli_pdf_standard_alt_text() {
+    let ctx = RenderContext::new("book", Book::new(), mdbook::Config::default(), "book/out");
+    let image = Image {
+      src: "images/photo.png",
+      path: Some("images/photo.png"),
+      ..Default::default()
+    };
+
+    let cfg = crate::load_config(&ctx, &crate::args::WorldArgs::default()).unwrap();
+    assert!(!image_typst(&cfg, &image).contains("alt:"));
+
+    let command = <crate::args::CliArguments as clap::Parser>::parse_from([
+      "mdbook-typst-pdf",
+      "build",
+      "--pdf-standard",
+      "a-2b",
+    ]);
+    let Some(crate::args::Command::Build(command)) = command.command else {
+      panic!("not a build command");
+    };
+
+    let cfg = crate::load_config(&ctx, &command.world_args).unwrap();
+    assert!(image_typst(&cfg, &image).contains("alt: \"photo\""));
+  }
+
+  #[test]
+  fn cache_reconverts_missing_outputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("ferris.svg");
+    fs::write(&output, "<svg/>").unwrap();
+
+    let mut cache = ChapterCache::default();
+    let mut conversions = 0;
+    let mut build = |cache: &mut ChapterCache| {
+      cache.begin(0);
+      cache
+        .get_or_convert(1, |files| {
+          conversions += 1;
+          files.record_output(&output);
+
+          Ok(Default::default())
+        })
+        .unwrap();
+    };
+
+    build(&mut cache);
+    build(&mut cache);
+    fs::remove_file(&output).unwrap();
+    build(&mut cache);
+
+    assert_eq!(conversions, 2);
+  }
 }
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::args::DiagnosticFormat;
+
+/// A diagnostic as printed with `diagnostic-format = "json"`, one per line.
+#[derive(Debug, Serialize)]
+pub struct JsonDiagnostic {
+  /// `error` or `warning`.
+  pub severity: &'static str,
+  pub message: String,
+  pub hints: Vec<String>,
+  /// Where the diagnostic points to, if anywhere.
+  #[serde(flatten)]
+  pub location: Option<JsonLocation>,
+  /// The Markdown the location was converted from.
+  pub markdown: Option<JsonLocation>,
+  /// Where the diagnostic's cause was called from, innermost first.
+  pub trace: Vec<JsonTracePoint>,
+}
+
+/// A position in a file, with 1-based line and column.
+#[derive(Debug, Serialize)]
+pub struct JsonLocation {
+  pub file: String,
+  pub line: usize,
+  pub column: Option<usize>,
+}
+
+/// A step of a diagnostic's trace.
+#[derive(Debug, Serialize)]
+pub struct JsonTracePoint {
+  pub message: String,
+  #[serde(flatten)]
+  pub location: Option<JsonLocation>,
+  pub markdown: Option<JsonLocation>,
+}
+
+impl JsonDiagnostic {
+  /// Print the diagnostic as a line of JSON to stderr.
+  pub fn emit(&self) {
+    match serde_json::to_string(self) {
+      Ok(json) => eprintln!("{}", json),
+      Err(err) => tracing::error!("failed to serialize diagnostic: {}", err),
+    }
+  }
+}
+
+/// Report a problem found while converting the Markdown file at `path`, at
+/// the 1-based `line`.
+pub fn conversion_warning(format: DiagnosticFormat, path: &Path, line: usize, message: &str) {
+  match format {
+    DiagnosticFormat::Human | DiagnosticFormat::Short => {
+      tracing::warn!("{}:{}: {}", path.display(), line, message)
+    }
+    DiagnosticFormat::Json => {
+      let location = || JsonLocation {
+        file: path.display().to_string(),
+        line,
+        column: None,
+      };
+
+      JsonDiagnostic {
+        severity: "warning",
+        message: message.to_string(),
+        hints: vec![],
+        location: Some(location()),
+        markdown: Some(location()),
+        trace: vec![],
+      }
+      .emit()
+    }
+  }
+}
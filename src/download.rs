@@ -8,14 +8,20 @@ use codespan_reporting::term::termcolor::WriteColor;
 use typst::utils::format_duration;
 use typst_kit::download::{DownloadState, Downloader, Progress};
 
+use crate::args::DiagnosticFormat;
 use crate::terminal::{self, TermOut};
 
 /// Prints download progress by writing `downloading {0}` followed by repeatedly
-/// updating the last terminal line.
-pub struct PrintDownload<T>(pub T);
+/// updating the last terminal line. Nothing is printed with the JSON
+/// diagnostic format, which keeps stderr to one diagnostic per line.
+pub struct PrintDownload<T>(pub T, pub DiagnosticFormat);
 
 impl<T: Display> Progress for PrintDownload<T> {
   fn print_start(&mut self) {
+    if self.1 == DiagnosticFormat::Json {
+      return;
+    }
+
     // Print that a package downloading is happening.
     let styles = term::Styles::default();
 
@@ -28,12 +34,20 @@ impl<T: Display> Progress for PrintDownload<T> {
   }
 
   fn print_progress(&mut self, state: &DownloadState) {
+    if self.1 == DiagnosticFormat::Json {
+      return;
+    }
+
     let mut out = terminal::out();
     let _ = out.clear_last_line();
     let _ = display_download_progress(&mut out, state);
   }
 
   fn print_finish(&mut self, state: &DownloadState) {
+    if self.1 == DiagnosticFormat::Json {
+      return;
+    }
+
     let mut out = terminal::out();
     let _ = display_download_progress(&mut out, state);
     let _ = writeln!(out);
@@ -51,11 +65,9 @@ pub fn downloader() -> Downloader {
 pub fn display_download_progress(out: &mut TermOut, state: &DownloadState) -> io::Result<()> {
   let sum: usize = state.bytes_per_second.iter().sum();
   let len = state.bytes_per_second.len();
-  let speed = if len > 0 {
-    sum / len
-  } else {
-    state.content_len.unwrap_or(0)
-  };
+  let speed = sum
+    .checked_div(len)
+    .unwrap_or_else(|| state.content_len.unwrap_or(0));
 
   let total_downloaded = as_bytes_unit(state.total_downloaded);
   let speed_h = as_throughput_unit(speed);
@@ -67,11 +79,7 @@ pub fn display_download_progress(out: &mut TermOut, state: &DownloadState) -> io
       let remaining = content_len - state.total_downloaded;
 
       let download_size = as_bytes_unit(content_len);
-      let eta = Duration::from_secs(if speed == 0 {
-        0
-      } else {
-        (remaining / speed) as u64
-      });
+      let eta = Duration::from_secs(remaining.checked_div(speed).unwrap_or(0) as u64);
 
       writeln!(
         out,
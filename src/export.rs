@@ -1,8 +1,11 @@
 use chrono::{Datelike, Timelike};
+use clap::ValueEnum;
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use codespan_reporting::term;
 use ecow::eco_format;
-use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
 use typst::diag::Warned;
 use typst::diag::{At, Severity, SourceDiagnostic, StrResult};
 use typst::foundations::Datetime;
@@ -11,41 +14,73 @@ use typst::syntax::{FileId, Source, Span};
 use typst::{World, WorldExt};
 use typst_pdf::{PdfOptions, PdfStandards};
 
-use crate::args::{DiagnosticFormat, SharedArgs};
+use crate::args::{DiagnosticFormat, PdfStandard, SharedArgs};
+use crate::diagnostic::{JsonDiagnostic, JsonLocation, JsonTracePoint};
+use crate::sourcemap::{Location, SourceMap};
 use crate::terminal;
 use crate::world::SystemWorld;
 
 type CodespanResult<T> = Result<T, CodespanError>;
 type CodespanError = codespan_reporting::files::Error;
 
-pub fn export_pdf(args: SharedArgs) -> StrResult<()> {
+pub fn export_pdf(args: SharedArgs, source_map: &SourceMap) -> StrResult<()> {
   let world = SystemWorld::new(&args).map_err(|err| eco_format!("{err}"))?;
 
+  compile_once(&world, &args, source_map)
+}
+
+/// Compile the main file of `world` once and write the PDF. Diagnostics in
+/// the main file are traced back to the Markdown through `source_map`.
+pub fn compile_once(
+  world: &SystemWorld,
+  args: &SharedArgs,
+  source_map: &SourceMap,
+) -> StrResult<()> {
   tracing::info!("Starting compilation");
 
   let start = std::time::Instant::now();
 
   // Check if main file can be read and opened.
   if let Err(errors) = world.source(world.main()).at(Span::detached()) {
-    print_diagnostics(&world, &errors, &[], DiagnosticFormat::Human)
+    print_diagnostics(world, source_map, &errors, &[], args.diagnostic_format)
       .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
 
     return Err(eco_format!("export_pdf failed"));
   }
 
-  let Warned { output, warnings } = typst::compile(&world);
+  let Warned { output, warnings } = typst::compile(world);
 
   let result = output.and_then(|document| {
     let options = PdfOptions {
-      ident: Smart::Auto,
-      timestamp: convert_datetime(chrono::Utc::now()),
+      ident: args.ident.as_deref().map_or(Smart::Auto, Smart::Custom),
+      timestamp: convert_datetime(args.creation_timestamp.unwrap_or_else(chrono::Utc::now)),
       page_ranges: None,
-      standards: pdf_standards().at(Span::detached())?,
+      standards: pdf_standards(args).at(Span::detached())?,
     };
 
-    let buffer = typst_pdf::pdf(&document, &options)?;
+    let buffer = typst_pdf::pdf(&document, &options).map_err(|errors| {
+      if args.pdf_standards.is_empty() {
+        return errors;
+      }
 
-    fs::write(args.output, buffer)
+      let hint = eco_format!(
+        "the PDF is exported with pdf-standard {}",
+        args
+          .pdf_standards
+          .iter()
+          .filter_map(|standard| standard.to_possible_value())
+          .map(|value| value.get_name().to_string())
+          .collect::<Vec<_>>()
+          .join(", ")
+      );
+
+      errors
+        .into_iter()
+        .map(|error| error.with_hint(hint.clone()))
+        .collect()
+    })?;
+
+    write_atomically(&args.output, &buffer)
       .map_err(|err| eco_format!("failed to write PDF file ({err})"))
       .at(Span::detached())?;
 
@@ -58,11 +93,11 @@ pub fn export_pdf(args: SharedArgs) -> StrResult<()> {
 
       tracing::info!("Compilation succeeded in {duration:?}");
 
-      print_diagnostics(&world, &[], &warnings, DiagnosticFormat::Human)
+      print_diagnostics(world, source_map, &[], &warnings, args.diagnostic_format)
         .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
     }
     Err(errors) => {
-      print_diagnostics(&world, &errors, &[], DiagnosticFormat::Human)
+      print_diagnostics(world, source_map, &errors, &[], args.diagnostic_format)
         .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
 
       return Err(eco_format!("export_pdf failed"));
@@ -72,32 +107,71 @@ pub fn export_pdf(args: SharedArgs) -> StrResult<()> {
   Ok(())
 }
 
+/// Write to a temporary file next to `path` and move it into place, so readers
+/// never observe a partially written file.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+  let dir = path
+    .parent()
+    .filter(|dir| !dir.as_os_str().is_empty())
+    .unwrap_or(Path::new("."));
+
+  let mut file = NamedTempFile::new_in(dir)?;
+  file.write_all(data)?;
+  file.persist(path).map_err(|err| err.error)?;
+
+  Ok(())
+}
+
 /// Print diagnostic messages to the terminal.
 pub fn print_diagnostics(
   world: &SystemWorld,
+  source_map: &SourceMap,
   errors: &[SourceDiagnostic],
   warnings: &[SourceDiagnostic],
   diagnostic_format: DiagnosticFormat,
 ) -> Result<(), codespan_reporting::files::Error> {
+  if diagnostic_format == DiagnosticFormat::Json {
+    for diagnostic in warnings.iter().chain(errors) {
+      json_diagnostic(world, source_map, diagnostic).emit();
+    }
+
+    return Ok(());
+  }
+
   let mut config = term::Config {
     tab_width: 2,
     ..Default::default()
   };
-  if diagnostic_format == DiagnosticFormat::Short {
+  let short = diagnostic_format == DiagnosticFormat::Short;
+  if short {
     config.display_style = term::DisplayStyle::Short;
   }
 
+  // The short style leaves out notes, so the Markdown position goes into the
+  // message there.
+  let message = |message: &str, span: Span| match markdown_location(world, source_map, span) {
+    Some(location) if short => format!(
+      "{} (converted from {}:{}:{})",
+      message,
+      location.path.display(),
+      location.line,
+      location.column
+    ),
+    _ => message.to_string(),
+  };
+
   for diagnostic in warnings.iter().chain(errors) {
     let diag = match diagnostic.severity {
       Severity::Error => Diagnostic::error(),
       Severity::Warning => Diagnostic::warning(),
     }
-    .with_message(diagnostic.message.clone())
+    .with_message(message(&diagnostic.message, diagnostic.span))
     .with_notes(
       diagnostic
         .hints
         .iter()
         .map(|e| (eco_format!("hint: {e}")).into())
+        .chain(markdown_note(world, source_map, diagnostic.span))
         .collect(),
     )
     .with_labels(label(world, diagnostic.span).into_iter().collect());
@@ -106,9 +180,13 @@ pub fn print_diagnostics(
 
     // Stacktrace-like helper diagnostics.
     for point in &diagnostic.trace {
-      let message = point.v.to_string();
       let help = Diagnostic::help()
-        .with_message(message)
+        .with_message(message(&point.v.to_string(), point.span))
+        .with_notes(
+          markdown_note(world, source_map, point.span)
+            .into_iter()
+            .collect(),
+        )
         .with_labels(label(world, point.span).into_iter().collect());
 
       term::emit(&mut terminal::out(), &config, world, &help)?;
@@ -118,6 +196,94 @@ pub fn print_diagnostics(
   Ok(())
 }
 
+/// Convert a diagnostic for the JSON diagnostic format.
+fn json_diagnostic(
+  world: &SystemWorld,
+  source_map: &SourceMap,
+  diagnostic: &SourceDiagnostic,
+) -> JsonDiagnostic {
+  JsonDiagnostic {
+    severity: match diagnostic.severity {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+    },
+    message: diagnostic.message.to_string(),
+    hints: diagnostic
+      .hints
+      .iter()
+      .map(|hint| hint.to_string())
+      .collect(),
+    location: json_location(world, diagnostic.span),
+    markdown: json_markdown_location(world, source_map, diagnostic.span),
+    trace: diagnostic
+      .trace
+      .iter()
+      .map(|point| JsonTracePoint {
+        message: point.v.to_string(),
+        location: json_location(world, point.span),
+        markdown: json_markdown_location(world, source_map, point.span),
+      })
+      .collect(),
+  }
+}
+
+/// The file, line and column a span points to.
+fn json_location(world: &SystemWorld, span: Span) -> Option<JsonLocation> {
+  let id = span.id()?;
+  let location =
+    codespan_reporting::files::Files::location(world, id, world.range(span)?.start).ok()?;
+
+  Some(JsonLocation {
+    file: codespan_reporting::files::Files::name(world, id).ok()?,
+    line: location.line_number,
+    column: Some(location.column_number),
+  })
+}
+
+/// The Markdown position a span in the main file was converted from.
+fn json_markdown_location(
+  world: &SystemWorld,
+  source_map: &SourceMap,
+  span: Span,
+) -> Option<JsonLocation> {
+  let location = markdown_location(world, source_map, span)?;
+
+  Some(JsonLocation {
+    file: location.path.display().to_string(),
+    line: location.line,
+    column: Some(location.column),
+  })
+}
+
+/// The Markdown a span in the main file was converted from.
+fn markdown_location<'a>(
+  world: &SystemWorld,
+  source_map: &'a SourceMap,
+  span: Span,
+) -> Option<Location<'a>> {
+  if span.id()? != world.main() {
+    return None;
+  }
+
+  source_map.lookup(world.range(span)?.start)
+}
+
+/// Point to the Markdown a span in the main file was converted from.
+fn markdown_note(world: &SystemWorld, source_map: &SourceMap, span: Span) -> Option<String> {
+  let location = markdown_location(world, source_map, span)?;
+
+  let line = location.line.to_string();
+
+  Some(format!(
+    "converted from {}:{}:{}\n{} | {}",
+    location.path.display(),
+    location.line,
+    location.column,
+    line,
+    location.text
+  ))
+}
+
 /// Create a label for a span.
 fn label(world: &SystemWorld, span: Span) -> Option<Label<FileId>> {
   Some(Label::primary(span.id()?, world.range(span)?))
@@ -182,8 +348,15 @@ impl<'a> codespan_reporting::files::Files<'a> for SystemWorld {
 }
 
 /// The PDF standards to try to conform with.
-fn pdf_standards() -> StrResult<PdfStandards> {
-  let list = vec![];
+fn pdf_standards(args: &SharedArgs) -> StrResult<PdfStandards> {
+  let list = args
+    .pdf_standards
+    .iter()
+    .map(|standard| match standard {
+      PdfStandard::V_1_7 => typst_pdf::PdfStandard::V_1_7,
+      PdfStandard::A_2b => typst_pdf::PdfStandard::A_2b,
+    })
+    .collect::<Vec<_>>();
 
   PdfStandards::new(&list)
 }
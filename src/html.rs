@@ -0,0 +1,1438 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+  self, BufferQueue, TagKind, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+
+use crate::convert::{escape_text, typst_string};
+
+/// Elements without content or end tag.
+const VOID_ELEMENTS: &[&str] = &[
+  "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+  "wbr",
+];
+
+/// Named colors Typst and CSS have in common.
+const NAMED_COLORS: &[&str] = &[
+  "black", "gray", "silver", "white", "navy", "blue", "aqua", "teal", "purple", "fuchsia",
+  "maroon", "red", "orange", "yellow", "olive", "green", "lime",
+];
+
+/// An HTML start tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlTag {
+  /// The lowercase element name.
+  pub name: String,
+  /// The attributes with lowercase names and decoded values.
+  pub attrs: Vec<(String, String)>,
+  pub self_closing: bool,
+}
+
+impl HtmlTag {
+  /// The value of an attribute.
+  pub fn attr(&self, name: &str) -> Option<&str> {
+    self
+      .attrs
+      .iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.as_str())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Start(HtmlTag),
+  End(String),
+  Text(String),
+}
+
+/// Collects the tokens of a fragment from the HTML tokenizer.
+#[derive(Default)]
+struct Tokens(RefCell<Vec<Token>>);
+
+impl Tokens {
+  fn take(&self) -> Vec<Token> {
+    self.0.take()
+  }
+}
+
+impl TokenSink for Tokens {
+  type Handle = ();
+
+  fn process_token(&self, token: tokenizer::Token, _line_number: u64) -> TokenSinkResult<()> {
+    let mut tokens = self.0.borrow_mut();
+
+    match token {
+      tokenizer::Token::TagToken(tag) => {
+        let name = tag.name.to_string();
+
+        if tag.kind == TagKind::EndTag {
+          tokens.push(Token::End(name));
+
+          return TokenSinkResult::Continue;
+        }
+
+        // The content of elements like `<script>` is never markup.
+        let raw = match name.as_str() {
+          "script" => Some(RawKind::ScriptData),
+          "style" => Some(RawKind::Rawtext),
+          "textarea" | "title" => Some(RawKind::Rcdata),
+          _ => None,
+        };
+
+        tokens.push(Token::Start(HtmlTag {
+          name,
+          attrs: tag
+            .attrs
+            .into_iter()
+            .map(|attr| (attr.name.local.to_string(), attr.value.to_string()))
+            .collect(),
+          self_closing: tag.self_closing,
+        }));
+
+        if let Some(kind) = raw.filter(|_| !tag.self_closing) {
+          return TokenSinkResult::RawData(kind);
+        }
+      }
+      tokenizer::Token::CharacterTokens(text) => match tokens.last_mut() {
+        Some(Token::Text(last)) => last.push_str(&text),
+        _ => tokens.push(Token::Text(text.to_string())),
+      },
+      // Comments, doctypes and parse errors are left out.
+      _ => {}
+    }
+
+    TokenSinkResult::Continue
+  }
+}
+
+/// What converting HTML needs from the chapter it is in.
+pub trait HtmlContext {
+  /// The opening of a link to `href`, up to and including the `[`.
+  fn link(&self, href: &str) -> String;
+
+  /// An `<img>` as Typst, `inline` if it is part of a table cell.
+  fn image(&self, tag: &HtmlTag, inline: bool) -> Result<String, anyhow::Error>;
+
+  /// Markdown inside HTML, like in a table cell, as Typst.
+  fn markdown(&self, text: &str) -> String;
+
+  /// Warn about HTML that can't be converted faithfully.
+  fn warn(&self, message: &str);
+}
+
+/// How the content of an open element is converted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Content {
+  /// Typst markup.
+  Markup,
+  /// The arguments of a function call, like the items of a list. Text
+  /// between them is left out.
+  Arguments,
+  /// A `<table>`, whose text outside of cells is left out.
+  Table,
+  /// A `<thead>`, `<tbody>` or `<tfoot>`.
+  Section {
+    head: bool,
+  },
+  Row,
+  Cell,
+  Caption,
+}
+
+impl Content {
+  /// Whether text in the element is kept.
+  fn keeps_text(self) -> bool {
+    matches!(self, Content::Markup | Content::Cell | Content::Caption)
+  }
+}
+
+/// How an element is converted.
+enum Element {
+  /// Written as `open`, the content and `close`.
+  Wrap {
+    open: String,
+    close: String,
+    content: Content,
+  },
+  /// An element without content.
+  Void(String),
+  /// Text collected for a `#raw`.
+  Raw { block: bool },
+  /// Left out with its content.
+  Skip,
+}
+
+struct OpenElement {
+  name: String,
+  close: String,
+  content: Content,
+}
+
+/// An HTML table, collected until its end tag as the number of columns is
+/// only known then.
+#[derive(Default)]
+struct Table {
+  rows: Vec<Row>,
+  /// Whether rows are in a `<thead>`.
+  head: bool,
+  row_open: bool,
+  /// The alignment of the cells in the open row.
+  row_align: Option<String>,
+  cell: Option<Cell>,
+  caption: Option<String>,
+  caption_open: bool,
+}
+
+struct Row {
+  header: bool,
+  cells: Vec<Cell>,
+}
+
+struct Cell {
+  content: String,
+  header: bool,
+  colspan: usize,
+  rowspan: usize,
+  align: Option<String>,
+}
+
+impl Table {
+  fn start_row(&mut self, align: Option<String>) {
+    self.rows.push(Row {
+      header: self.head,
+      cells: vec![],
+    });
+    self.row_open = true;
+    self.row_align = align;
+  }
+
+  fn start_cell(&mut self, tag: &HtmlTag) {
+    if !self.row_open {
+      self.start_row(None);
+    }
+
+    let span = |name: &str| {
+      tag
+        .attr(name)
+        .and_then(|span| span.trim().parse::<usize>().ok())
+        .map_or(1, |span| span.clamp(1, 1000))
+    };
+
+    self.cell = Some(Cell {
+      content: String::new(),
+      header: tag.name == "th",
+      colspan: span("colspan"),
+      rowspan: span("rowspan"),
+      align: alignment(tag).or_else(|| self.row_align.clone()),
+    });
+  }
+
+  fn end_cell(&mut self) {
+    if let (Some(cell), Some(row)) = (self.cell.take(), self.rows.last_mut()) {
+      row.cells.push(cell);
+    }
+  }
+
+  /// Where Typst in the table is written to, nowhere outside of cells and
+  /// the caption.
+  fn buffer(&mut self) -> Option<&mut String> {
+    if self.caption_open {
+      self.caption.as_mut()
+    } else {
+      self.cell.as_mut().map(|cell| &mut cell.content)
+    }
+  }
+
+  /// The number of columns, taking cells spanning rows and columns into
+  /// account.
+  fn columns(&self) -> usize {
+    // The number of rows each column is still taken for by a cell above.
+    let mut taken: Vec<usize> = vec![];
+    let mut columns = 1;
+
+    for row in &self.rows {
+      let mut column = 0;
+
+      for cell in &row.cells {
+        while taken.get(column).is_some_and(|&rows| rows > 0) {
+          column += 1;
+        }
+
+        if taken.len() < column + cell.colspan {
+          taken.resize(column + cell.colspan, 0);
+        }
+        taken[column..column + cell.colspan].fill(cell.rowspan);
+
+        column += cell.colspan;
+      }
+
+      columns = columns.max(
+        taken
+          .iter()
+          .rposition(|&rows| rows > 0)
+          .map_or(0, |i| i + 1),
+      );
+
+      for rows in &mut taken {
+        *rows = rows.saturating_sub(1);
+      }
+    }
+
+    columns
+  }
+
+  fn typst(&self) -> String {
+    // Leading rows in a `<thead>` or of `<th>` only repeat on every page.
+    let header_rows = self
+      .rows
+      .iter()
+      .take_while(|row| {
+        row.header || (!row.cells.is_empty() && row.cells.iter().all(|cell| cell.header))
+      })
+      .count();
+
+    let mut table = format!("table(\n  columns: {},\n  inset: 10pt,\n", self.columns());
+
+    if header_rows > 0 {
+      table.push_str("  table.header(\n");
+      for cell in self.rows[..header_rows].iter().flat_map(|row| &row.cells) {
+        table.push_str(&format!("    {},\n", cell.typst()));
+      }
+      table.push_str("  ),\n");
+    }
+
+    for cell in self.rows[header_rows..].iter().flat_map(|row| &row.cells) {
+      table.push_str(&format!("  {},\n", cell.typst()));
+    }
+
+    table.push(')');
+
+    match &self.caption {
+      Some(caption) => format!(
+        "\n#figure(\n  {},\n  caption: [{}],\n)\n",
+        table.replace('\n', "\n  "),
+        caption.trim()
+      ),
+      None => format!("\n#{}\n", table),
+    }
+  }
+}
+
+impl Cell {
+  fn typst(&self) -> String {
+    let content = self.content.trim();
+
+    let content = if self.header && !content.is_empty() {
+      format!("#strong[{}]", content)
+    } else {
+      content.to_string()
+    };
+
+    let mut args = vec![];
+    if self.colspan > 1 {
+      args.push(format!("colspan: {}", self.colspan));
+    }
+    if self.rowspan > 1 {
+      args.push(format!("rowspan: {}", self.rowspan));
+    }
+    if let Some(align) = &self.align {
+      args.push(format!("align: {}", align));
+    }
+
+    if args.is_empty() {
+      format!("[{}]", content)
+    } else {
+      format!("table.cell({})[{}]", args.join(", "), content)
+    }
+  }
+}
+
+/// The text of a `<code>` or `<pre>` element.
+struct RawText {
+  name: String,
+  block: bool,
+  lang: Option<String>,
+  text: String,
+}
+
+/// Converts the raw HTML of a chapter to Typst. HTML can be split across
+/// several Markdown events with Markdown in between, so elements stay open
+/// until their end tag shows up.
+pub struct HtmlConverter {
+  open: Vec<OpenElement>,
+  raw: Option<RawText>,
+  /// The open tables, innermost last.
+  tables: Vec<Table>,
+  /// The element whose content is left out.
+  skipped: Option<String>,
+  /// Tokenizes the HTML of an HTML block, keeping a tag that continues in the
+  /// next event.
+  tokenizer: Tokenizer<Tokens>,
+  input: BufferQueue,
+  /// Elements that have been warned about.
+  warned: HashSet<String>,
+}
+
+impl Default for HtmlConverter {
+  fn default() -> Self {
+    Self {
+      open: Vec::new(),
+      raw: None,
+      tables: Vec::new(),
+      skipped: None,
+      tokenizer: Tokenizer::new(Tokens::default(), TokenizerOpts::default()),
+      input: BufferQueue::default(),
+      warned: HashSet::new(),
+    }
+  }
+}
+
+impl HtmlConverter {
+  /// Convert an HTML fragment.
+  pub fn convert(&mut self, html: &str, cx: &impl HtmlContext) -> Result<String, anyhow::Error> {
+    self.input.push_back(StrTendril::from_slice(html));
+
+    // The sink never asks to run a script, so the whole input is tokenized.
+    let _ = self.tokenizer.feed(&self.input);
+
+    let mut out = String::new();
+
+    for token in self.tokenizer.sink.take() {
+      match token {
+        Token::Start(tag) => self.start(tag, cx, &mut out)?,
+        Token::End(name) => self.end(&name, &mut out),
+        Token::Text(text) => self.text(&text, cx, &mut out),
+      }
+    }
+
+    Ok(out)
+  }
+
+  /// End an HTML block. A tag left incomplete at its end is dropped, the way
+  /// a browser drops it at the end of a document.
+  pub fn flush(&mut self, cx: &impl HtmlContext) -> String {
+    self.tokenizer.end();
+
+    let tokens = self.tokenizer.sink.take();
+    self.tokenizer = Tokenizer::new(Tokens::default(), TokenizerOpts::default());
+
+    // Only text is left at the end of the input, like a lone `<`.
+    let mut out = String::new();
+    for token in tokens {
+      if let Token::Text(text) = token {
+        self.text(&text, cx, &mut out);
+      }
+    }
+
+    out
+  }
+
+  /// Close the elements left open at the end of the chapter.
+  pub fn finish(&mut self, cx: &impl HtmlContext) -> String {
+    let mut out = self.flush(cx);
+
+    if let Some(raw) = self.raw.take() {
+      cx.warn(&format!("HTML element `<{}>` is not closed", raw.name));
+
+      self.write(&mut out, &raw_call(&raw));
+    }
+
+    if let Some(name) = self.skipped.take() {
+      cx.warn(&format!("HTML element `<{}>` is not closed", name));
+    }
+
+    while let Some(element) = self.open.pop() {
+      cx.warn(&format!("HTML element `<{}>` is not closed", element.name));
+
+      self.close(element, &mut out);
+    }
+
+    out
+  }
+
+  /// Whether Typst written for the chapter belongs into an HTML table.
+  pub fn in_table(&self) -> bool {
+    !self.tables.is_empty()
+  }
+
+  /// Move Typst converted from Markdown inside an HTML table into its open
+  /// cell.
+  pub fn capture(&mut self, typst: &str) {
+    self.write(&mut String::new(), typst);
+  }
+
+  /// Write Typst to `out`, or to the open cell or caption of a table.
+  fn write(&mut self, out: &mut String, typst: &str) {
+    match self.tables.last_mut() {
+      Some(table) => {
+        if let Some(buffer) = table.buffer() {
+          buffer.push_str(typst);
+        }
+      }
+      None => out.push_str(typst),
+    }
+  }
+
+  /// Whether Typst written next starts a line.
+  fn at_line_start(&mut self, out: &str) -> bool {
+    let written = match self.tables.last_mut() {
+      Some(table) => table.buffer().map_or("", |buffer| buffer.as_str()),
+      None => out,
+    };
+
+    written.is_empty() || written.ends_with('\n')
+  }
+
+  fn close(&mut self, element: OpenElement, out: &mut String) {
+    match element.content {
+      Content::Table => {
+        if let Some(table) = self.tables.pop() {
+          self.write(out, &table.typst());
+        }
+
+        self.write(out, &element.close);
+      }
+      Content::Section { .. } => {
+        if let Some(table) = self.tables.last_mut() {
+          table.head = false;
+          table.row_open = false;
+        }
+      }
+      Content::Row => {
+        if let Some(table) = self.tables.last_mut() {
+          table.row_open = false;
+        }
+      }
+      Content::Cell => {
+        self.write(out, &element.close);
+
+        if let Some(table) = self.tables.last_mut() {
+          table.end_cell();
+        }
+      }
+      Content::Caption => {
+        self.write(out, &element.close);
+
+        if let Some(table) = self.tables.last_mut() {
+          table.caption_open = false;
+        }
+      }
+      Content::Markup | Content::Arguments => self.write(out, &element.close),
+    }
+  }
+
+  /// How the content of the innermost open element is converted.
+  fn content(&self) -> Content {
+    self
+      .open
+      .last()
+      .map_or(Content::Markup, |element| element.content)
+  }
+
+  fn start(
+    &mut self,
+    tag: HtmlTag,
+    cx: &impl HtmlContext,
+    out: &mut String,
+  ) -> Result<(), anyhow::Error> {
+    if self.skipped.is_some() {
+      return Ok(());
+    }
+
+    if let Some(raw) = &mut self.raw {
+      match tag.name.as_str() {
+        "br" => raw.text.push('\n'),
+        "code" if raw.block && raw.lang.is_none() => raw.lang = code_lang(&tag),
+        _ => (),
+      }
+
+      return Ok(());
+    }
+
+    // Elements whose end tag may be omitted.
+    let implied_end = match tag.name.as_str() {
+      "li" => &["li"][..],
+      "dt" | "dd" => &["dt", "dd"][..],
+      "p" => &["p"][..],
+      "td" | "th" => &["td", "th"][..],
+      "tr" => &["td", "th", "tr"][..],
+      "thead" | "tbody" | "tfoot" => &["td", "th", "tr", "thead", "tbody", "tfoot"][..],
+      _ => &[][..],
+    };
+    while self
+      .open
+      .last()
+      .is_some_and(|element| implied_end.contains(&element.name.as_str()))
+    {
+      if let Some(element) = self.open.pop() {
+        self.close(element, out);
+      }
+    }
+
+    let void = tag.self_closing || VOID_ELEMENTS.contains(&tag.name.as_str());
+
+    match self.element(&tag, cx)? {
+      Element::Wrap {
+        open,
+        close,
+        content,
+      } => {
+        if void {
+          return Ok(());
+        }
+
+        let table_part = matches!(
+          content,
+          Content::Section { .. } | Content::Row | Content::Cell | Content::Caption
+        );
+
+        let (style_open, style_close) = presentation(&tag, !table_part);
+
+        match content {
+          Content::Section { head } => {
+            if let Some(table) = self.tables.last_mut() {
+              table.head = head;
+              table.row_open = false;
+            }
+          }
+          Content::Row => {
+            if let Some(table) = self.tables.last_mut() {
+              table.start_row(alignment(&tag));
+            }
+          }
+          Content::Cell => {
+            if let Some(table) = self.tables.last_mut() {
+              table.start_cell(&tag);
+            }
+          }
+          Content::Caption => {
+            if let Some(table) = self.tables.last_mut() {
+              table.caption = Some(String::new());
+              table.caption_open = true;
+            }
+          }
+          _ => (),
+        }
+
+        self.write(out, &open);
+        self.write(out, &style_open);
+
+        if content == Content::Table {
+          self.tables.push(Table::default());
+        }
+
+        self.open.push(OpenElement {
+          name: tag.name,
+          close: format!("{}{}", style_close, close),
+          content,
+        });
+      }
+      Element::Void(typst) => self.write(out, &typst),
+      Element::Raw { block } => {
+        if !void {
+          self.raw = Some(RawText {
+            name: tag.name.clone(),
+            block,
+            lang: code_lang(&tag),
+            text: String::new(),
+          });
+        }
+      }
+      Element::Skip => {
+        if !void {
+          self.skipped = Some(tag.name);
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// How an element is converted to Typst.
+  fn element(&mut self, tag: &HtmlTag, cx: &impl HtmlContext) -> Result<Element, anyhow::Error> {
+    let wrap = |open: &str, close: &str| Element::Wrap {
+      open: open.to_string(),
+      close: close.to_string(),
+      content: Content::Markup,
+    };
+
+    let element = match tag.name.as_str() {
+      "a" => match tag.attr("href") {
+        Some(href) => wrap(&cx.link(href), "];"),
+        None => wrap("", ""),
+      },
+      "b" | "strong" => wrap("#strong[", "];"),
+      "i" | "em" | "cite" | "var" | "dfn" => wrap("#emph[", "];"),
+      "u" | "ins" => wrap("#underline[", "];"),
+      "s" | "strike" | "del" => wrap("#strike[", "];"),
+      "sup" => wrap("#super[", "];"),
+      "sub" => wrap("#sub[", "];"),
+      "mark" => wrap("#highlight[", "];"),
+      "small" => wrap("#text(size: 0.8em)[", "];"),
+      "big" => wrap("#text(size: 1.2em)[", "];"),
+      "q" => wrap("#quote[", "];"),
+      "kbd" => wrap(
+        "#box(stroke: 0.5pt + luma(160), inset: (x: 3pt), outset: (y: 3pt), radius: 2pt)[",
+        "];",
+      ),
+      "br" => Element::Void("#linebreak();".to_string()),
+      "hr" => Element::Void("\n#line(length: 100%)\n".to_string()),
+      "img" => Element::Void(cx.image(tag, self.in_table())?),
+      "input" if tag.attr("type") == Some("checkbox") => Element::Void(
+        if tag.attr("checked").is_some() {
+          "☑"
+        } else {
+          "☐"
+        }
+        .to_string(),
+      ),
+      "wbr" | "meta" | "link" | "source" | "track" | "col" | "area" | "base" => {
+        Element::Void(String::new())
+      }
+      "code" | "tt" | "samp" => Element::Raw { block: false },
+      "pre" => Element::Raw { block: true },
+      "p" | "div" | "section" | "article" | "main" | "header" | "footer" | "nav" | "aside"
+      | "figure" | "figcaption" | "address" | "dl" => wrap("\n\n", "\n\n"),
+      "center" => wrap("#align(center)[", "];"),
+      "blockquote" => wrap("#quote(block: true)[", "];"),
+      "details" => wrap(
+        "#block(width: 100%, inset: 10pt, radius: 4pt, stroke: luma(200))[",
+        "];",
+      ),
+      "summary" => wrap("#strong[", "];\n\n"),
+      "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => wrap(
+        &format!("\n#heading(level: {}, outlined: false)[", &tag.name[1..]),
+        "];\n",
+      ),
+      "ul" => Element::Wrap {
+        open: "#list(".to_string(),
+        close: ");".to_string(),
+        content: Content::Arguments,
+      },
+      "ol" => Element::Wrap {
+        open: match tag
+          .attr("start")
+          .and_then(|start| start.parse::<usize>().ok())
+        {
+          Some(start) => format!("#enum(start: {}, ", start),
+          None => "#enum(".to_string(),
+        },
+        close: ");".to_string(),
+        content: Content::Arguments,
+      },
+      "li" if self.content() == Content::Arguments => wrap("[", "], "),
+      "li" => wrap("#list.item[", "];"),
+      "dt" => wrap("#strong[", "];\n\n"),
+      "dd" => wrap("#pad(left: 1.5em)[", "];"),
+      "span" | "font" | "abbr" | "acronym" | "time" | "data" | "label" | "bdi" | "bdo" | "html"
+      | "body" | "picture" | "noscript" => wrap("", ""),
+      "table" => Element::Wrap {
+        open: String::new(),
+        close: String::new(),
+        content: Content::Table,
+      },
+      "thead" | "tbody" | "tfoot" if self.in_table() => Element::Wrap {
+        open: String::new(),
+        close: String::new(),
+        content: Content::Section {
+          head: tag.name == "thead",
+        },
+      },
+      "tr" if self.in_table() => Element::Wrap {
+        open: String::new(),
+        close: String::new(),
+        content: Content::Row,
+      },
+      "td" | "th" if self.in_table() => Element::Wrap {
+        open: String::new(),
+        close: String::new(),
+        content: Content::Cell,
+      },
+      "caption" if self.in_table() => Element::Wrap {
+        open: String::new(),
+        close: String::new(),
+        content: Content::Caption,
+      },
+      "colgroup" => Element::Skip,
+      "head" | "title" | "script" | "style" | "template" => Element::Skip,
+      "iframe" | "video" | "audio" | "object" | "embed" | "canvas" | "svg" | "math" | "form"
+      | "input" | "button" | "select" | "textarea" => {
+        self.warn_once(
+          cx,
+          &tag.name,
+          &format!(
+            "HTML element `<{}>` can't be rendered in a PDF and is left out",
+            tag.name
+          ),
+        );
+
+        Element::Skip
+      }
+      _ => {
+        self.warn_once(
+          cx,
+          &tag.name,
+          &format!(
+            "HTML element `<{}>` is not supported, only its text is kept",
+            tag.name
+          ),
+        );
+
+        wrap("", "")
+      }
+    };
+
+    Ok(element)
+  }
+
+  fn end(&mut self, name: &str, out: &mut String) {
+    if let Some(skipped) = &self.skipped {
+      if skipped == name {
+        self.skipped = None;
+      }
+
+      return;
+    }
+
+    if let Some(raw) = &self.raw {
+      if raw.name == name {
+        let typst = raw_call(raw);
+        self.write(out, &typst);
+
+        self.raw = None;
+      }
+
+      return;
+    }
+
+    // End tags without an open element are left out, elements opened
+    // inside are closed with it.
+    let Some(index) = self.open.iter().rposition(|element| element.name == name) else {
+      return;
+    };
+
+    let closed: Vec<_> = self.open.drain(index..).collect();
+    for element in closed.into_iter().rev() {
+      self.close(element, out);
+    }
+  }
+
+  fn text(&mut self, text: &str, cx: &impl HtmlContext, out: &mut String) {
+    if self.skipped.is_some() {
+      return;
+    }
+
+    if let Some(raw) = &mut self.raw {
+      raw.text.push_str(text);
+
+      return;
+    }
+
+    if !self.content().keeps_text() {
+      return;
+    }
+
+    // Markup at the start of a line would turn the text into a list or
+    // heading.
+    let at_line_start = self.at_line_start(out);
+
+    // HTML collapses whitespace.
+    let mut collapsed = String::with_capacity(text.len());
+    let mut space = false;
+    for ch in text.chars() {
+      if ch.is_ascii_whitespace() {
+        space = true;
+
+        continue;
+      }
+
+      if space && !(at_line_start && collapsed.is_empty()) {
+        collapsed.push(' ');
+      }
+
+      space = false;
+      collapsed.push(ch);
+    }
+    if space && !(at_line_start && collapsed.is_empty()) {
+      collapsed.push(' ');
+    }
+
+    // Cells often hold Markdown, as tables with cells spanning rows or
+    // columns can't be written in Markdown.
+    if self.tables.last().is_some_and(|table| table.cell.is_some()) && !collapsed.trim().is_empty()
+    {
+      let trimmed = collapsed.trim();
+      let typst = format!(
+        "{}{}{}",
+        &collapsed[..collapsed.len() - collapsed.trim_start().len()],
+        cx.markdown(trimmed),
+        &collapsed[collapsed.trim_end().len()..]
+      );
+
+      self.write(out, &typst);
+
+      return;
+    }
+
+    let mut escaped = escape_text(&collapsed);
+
+    if at_line_start {
+      let digits = escaped.len()
+        - escaped
+          .trim_start_matches(|ch: char| ch.is_ascii_digit())
+          .len();
+
+      if escaped.starts_with(['-', '+', '=', '/']) {
+        escaped.insert(0, '\\');
+      } else if digits > 0 && escaped[digits..].starts_with('.') {
+        escaped.insert(digits, '\\');
+      }
+    }
+
+    self.write(out, &escaped);
+  }
+
+  fn warn_once(&mut self, cx: &impl HtmlContext, name: &str, message: &str) {
+    if self.warned.insert(name.to_string()) {
+      cx.warn(message);
+    }
+  }
+}
+
+/// Wrappers for the presentational attributes of an element: `style`,
+/// `align` and the `color` of `<font>`. Alignment is left out for table
+/// parts, whose cells are aligned by the table.
+fn presentation(tag: &HtmlTag, with_align: bool) -> (String, String) {
+  let mut align = tag.attr("align").map(str::to_ascii_lowercase);
+  let mut highlight = None;
+  let mut underline = false;
+  let mut strike = false;
+  let mut text_args = vec![];
+
+  if let Some(color) = tag.attr("color").and_then(css_color) {
+    text_args.push(format!("fill: {}", color));
+  }
+
+  for declaration in tag.attr("style").unwrap_or_default().split(';') {
+    let Some((property, value)) = declaration.split_once(':') else {
+      continue;
+    };
+
+    let value = value.trim().to_ascii_lowercase();
+
+    match property.trim().to_ascii_lowercase().as_str() {
+      "color" => text_args.extend(css_color(&value).map(|color| format!("fill: {}", color))),
+      "background" | "background-color" => highlight = css_color(&value),
+      "font-weight"
+        if value == "bold"
+          || value == "bolder"
+          || value.parse::<u16>().is_ok_and(|weight| weight >= 600) =>
+      {
+        text_args.push("weight: \"bold\"".to_string())
+      }
+      "font-style" if value == "italic" || value == "oblique" => {
+        text_args.push("style: \"italic\"".to_string())
+      }
+      "text-decoration" | "text-decoration-line" => {
+        underline |= value.contains("underline");
+        strike |= value.contains("line-through");
+      }
+      "text-align" => align = Some(value),
+      _ => (),
+    }
+  }
+
+  let mut open = String::new();
+  let mut close = String::new();
+
+  let mut wrap = |call: String| {
+    open.push_str(&call);
+    close.insert_str(0, "];");
+  };
+
+  match align.as_deref().filter(|_| with_align) {
+    Some(align @ ("left" | "right" | "center" | "start" | "end")) => {
+      wrap(format!("#align({})[", align))
+    }
+    Some("justify") => wrap("#par(justify: true)[".to_string()),
+    _ => (),
+  }
+  if let Some(color) = highlight {
+    wrap(format!("#highlight(fill: {})[", color));
+  }
+  if underline {
+    wrap("#underline[".to_string());
+  }
+  if strike {
+    wrap("#strike[".to_string());
+  }
+  if !text_args.is_empty() {
+    wrap(format!("#text({})[", text_args.join(", ")));
+  }
+
+  (open, close)
+}
+
+/// The alignment of a table cell or row from its `align`, `valign` and
+/// `style` attributes.
+fn alignment(tag: &HtmlTag) -> Option<String> {
+  let mut horizontal = tag.attr("align").map(str::to_ascii_lowercase);
+  let mut vertical = tag.attr("valign").map(str::to_ascii_lowercase);
+
+  for declaration in tag.attr("style").unwrap_or_default().split(';') {
+    let Some((property, value)) = declaration.split_once(':') else {
+      continue;
+    };
+
+    match property.trim().to_ascii_lowercase().as_str() {
+      "text-align" => horizontal = Some(value.trim().to_ascii_lowercase()),
+      "vertical-align" => vertical = Some(value.trim().to_ascii_lowercase()),
+      _ => (),
+    }
+  }
+
+  let horizontal = match horizontal.as_deref() {
+    Some(align @ ("left" | "right" | "center" | "start" | "end")) => Some(align),
+    _ => None,
+  };
+  let vertical = match vertical.as_deref() {
+    Some("top") => Some("top"),
+    Some("middle") => Some("horizon"),
+    Some("bottom") => Some("bottom"),
+    _ => None,
+  };
+
+  match (horizontal, vertical) {
+    (Some(horizontal), Some(vertical)) => Some(format!("{} + {}", horizontal, vertical)),
+    (Some(align), None) | (None, Some(align)) => Some(align.to_string()),
+    (None, None) => None,
+  }
+}
+
+/// The width and height of an `<img>` from its attributes or style.
+pub fn image_size(tag: &HtmlTag) -> (Option<String>, Option<String>) {
+  let mut width = tag.attr("width").and_then(typst_length);
+  let mut height = tag.attr("height").and_then(typst_length);
+
+  for declaration in tag.attr("style").unwrap_or_default().split(';') {
+    let Some((property, value)) = declaration.split_once(':') else {
+      continue;
+    };
+
+    match property.trim().to_ascii_lowercase().as_str() {
+      "width" => width = typst_length(value).or(width),
+      "height" => height = typst_length(value).or(height),
+      _ => (),
+    }
+  }
+
+  (width, height)
+}
+
+/// Convert an HTML or CSS length to Typst, taking a pixel as 0.75pt.
+fn typst_length(value: &str) -> Option<String> {
+  let value = value.trim().to_ascii_lowercase();
+
+  let number_len = value
+    .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+    .unwrap_or(value.len());
+  let number: f64 = value[..number_len].parse().ok()?;
+
+  match value[number_len..].trim() {
+    "" | "px" => Some(format!("{}pt", number * 0.75)),
+    unit @ ("%" | "pt" | "mm" | "cm" | "in" | "em") => Some(format!("{}{}", number, unit)),
+    _ => None,
+  }
+}
+
+/// Convert a CSS color to Typst. Colors Typst has no equivalent for are
+/// ignored.
+fn css_color(value: &str) -> Option<String> {
+  let value = value.trim().to_ascii_lowercase();
+
+  if let Some(hex) = value.strip_prefix('#') {
+    return (matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|ch| ch.is_ascii_hexdigit()))
+      .then(|| format!("rgb(\"#{}\")", hex));
+  }
+
+  if let Some(args) = value
+    .strip_prefix("rgba(")
+    .or_else(|| value.strip_prefix("rgb("))
+    .and_then(|args| args.strip_suffix(')'))
+  {
+    let components: Vec<&str> = args
+      .split([',', ' ', '/'])
+      .filter(|component| !component.is_empty())
+      .collect();
+
+    if !(3..=4).contains(&components.len()) {
+      return None;
+    }
+
+    let mut typst_components = vec![];
+    for (i, component) in components.iter().enumerate() {
+      typst_components.push(match component.strip_suffix('%') {
+        Some(percent) => format!("{}%", percent.parse::<f64>().ok()?),
+        None if i == 3 => format!("{}%", component.parse::<f64>().ok()? * 100.0),
+        None => component.parse::<u8>().ok()?.to_string(),
+      });
+    }
+
+    return Some(format!("rgb({})", typst_components.join(", ")));
+  }
+
+  let value = if value == "grey" { "gray" } else { &value };
+
+  NAMED_COLORS.contains(&value).then(|| value.to_string())
+}
+
+/// The language of a `<code>` or `<pre>` from its `language-*` class.
+fn code_lang(tag: &HtmlTag) -> Option<String> {
+  tag
+    .attr("class")?
+    .split_ascii_whitespace()
+    .find_map(|class| {
+      class
+        .strip_prefix("language-")
+        .or_else(|| class.strip_prefix("lang-"))
+        .map(str::to_string)
+    })
+}
+
+/// The `#raw` for the text of a `<code>` or `<pre>`.
+fn raw_call(raw: &RawText) -> String {
+  let mut args = vec![];
+
+  if raw.block {
+    args.push("block: true".to_string());
+  }
+
+  if let Some(lang) = &raw.lang {
+    args.push(format!("lang: {}", typst_string(lang)));
+  }
+
+  if raw.block {
+    // A newline right after `<pre>` is not part of the text.
+    let text = raw.text.strip_prefix('\n').unwrap_or(&raw.text);
+
+    args.push(typst_string(text.trim_end()));
+
+    format!("\n#raw({})\n", args.join(", "))
+  } else {
+    args.push(typst_string(&raw.text));
+
+    format!("#raw({});", args.join(", "))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+
+  use super::*;
+
+  #[derive(Default)]
+  struct TestContext {
+    warnings: RefCell<Vec<String>>,
+  }
+
+  impl HtmlContext for TestContext {
+    fn link(&self, href: &str) -> String {
+      format!("#link({})[", typst_string(href))
+    }
+
+    fn image(&self, tag: &HtmlTag, _inline: bool) -> Result<String, anyhow::Error> {
+      Ok(format!(
+        "#image({})",
+        typst_string(tag.attr("src").unwrap_or_default())
+      ))
+    }
+
+    fn markdown(&self, text: &str) -> String {
+      escape_text(text)
+    }
+
+    fn warn(&self, message: &str) {
+      self.warnings.borrow_mut().push(message.to_string());
+    }
+  }
+
+  /// Convert HTML fragments as one chapter, returning the Typst and the
+  /// warnings.
+  fn convert(fragments: &[&str]) -> (String, Vec<String>) {
+    let cx = TestContext::default();
+    let mut html = HtmlConverter::default();
+
+    let mut typst = String::new();
+    for fragment in fragments {
+      typst.push_str(&html.convert(fragment, &cx).unwrap());
+    }
+    typst.push_str(&html.finish(&cx));
+
+    (typst, cx.warnings.into_inner())
+  }
+
+  /// Tokenize HTML as a whole.
+  fn tokenize(html: &str) -> Vec<Token> {
+    let tokenizer = Tokenizer::new(Tokens::default(), TokenizerOpts::default());
+    let input = BufferQueue::default();
+    input.push_back(StrTendril::from_slice(html));
+
+    let _ = tokenizer.feed(&input);
+    tokenizer.end();
+
+    tokenizer.sink.take()
+  }
+
+  fn tag(name: &str, attrs: &[(&str, &str)], self_closing: bool) -> Token {
+    Token::Start(HtmlTag {
+      name: name.to_string(),
+      attrs: attrs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect(),
+      self_closing,
+    })
+  }
+
+  #[test]
+  fn entities() {
+    assert_eq!(
+      tokenize("a &amp; b &lt;c&gt; &quot;d&quot; &nbsp;&mdash;"),
+      vec![Token::Text("a & b <c> \"d\" \u{a0}—".to_string())]
+    );
+    assert_eq!(
+      tokenize("&#65;&#x42;&#X43; &unknown; & &amp"),
+      vec![Token::Text("ABC &unknown; & &".to_string())]
+    );
+  }
+
+  #[test]
+  fn attributes() {
+    assert_eq!(
+      tokenize(r#"<A HREF="x y" title='it&apos;s' data-n=3 checked>"#),
+      vec![tag(
+        "a",
+        &[
+          ("href", "x y"),
+          ("title", "it's"),
+          ("data-n", "3"),
+          ("checked", "")
+        ],
+        false
+      )]
+    );
+
+    assert_eq!(
+      tokenize("<img src = a.png alt=\"a > b\"/>"),
+      vec![tag("img", &[("src", "a.png"), ("alt", "a > b")], true)]
+    );
+  }
+
+  #[test]
+  fn incomplete_tag_continues_in_next_fragment() {
+    let (typst, _) = convert(&["text <a href=\"x", "\">link</a>"]);
+
+    assert_eq!(typst, "text #link(\"x\")[link];");
+
+    let (typst, _) = convert(&["a &am", "p; b"]);
+
+    assert_eq!(typst, "a & b");
+  }
+
+  #[test]
+  fn incomplete_tag_at_end_of_block() {
+    let cx = TestContext::default();
+    let mut html = HtmlConverter::default();
+
+    assert_eq!(html.convert("a <b", &cx).unwrap(), "a ");
+    assert_eq!(html.flush(&cx), "");
+    assert_eq!(html.convert("<i>b</i> <", &cx).unwrap(), "#emph[b]; ");
+    assert_eq!(html.flush(&cx), "\\<");
+  }
+
+  #[test]
+  fn text_without_tags() {
+    assert_eq!(
+      tokenize("a < b, 1<2"),
+      vec![Token::Text("a < b, 1<2".to_string())]
+    );
+  }
+
+  #[test]
+  fn void_and_self_closing_tags() {
+    let (typst, _) = convert(&["a<br>b<br/>c<hr>d"]);
+
+    assert_eq!(
+      typst,
+      "a#linebreak();b#linebreak();c\n#line(length: 100%)\nd"
+    );
+
+    // A self-closing element other than a void one has no content.
+    let (typst, warnings) = convert(&["<b/>text"]);
+
+    assert_eq!(typst, "text");
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn unclosed_tags() {
+    let (typst, warnings) = convert(&["<b>bold <i>both"]);
+
+    assert_eq!(typst, "#strong[bold #emph[both];];");
+    assert_eq!(
+      warnings,
+      vec![
+        "HTML element `<i>` is not closed",
+        "HTML element `<b>` is not closed"
+      ]
+    );
+  }
+
+  #[test]
+  fn misnested_tags() {
+    let (typst, warnings) = convert(&["<b><i>x</b>y</i>"]);
+
+    assert_eq!(typst, "#strong[#emph[x];];y");
+    assert!(warnings.is_empty());
+
+    let (typst, _) = convert(&["</b>text</span>"]);
+
+    assert_eq!(typst, "text");
+  }
+
+  #[test]
+  fn implied_end_tags() {
+    let (typst, _) = convert(&["<ul><li>a<li>b</ul>"]);
+
+    assert_eq!(typst, "#list([a], [b], );");
+  }
+
+  #[test]
+  fn comments() {
+    let (typst, _) = convert(&["a<!-- <b> -->b<!DOCTYPE html>c"]);
+
+    assert_eq!(typst, "abc");
+
+    let (typst, _) = convert(&["a<!-- <b>", " -->b"]);
+
+    assert_eq!(typst, "ab");
+  }
+
+  #[test]
+  fn raw_text_elements() {
+    assert_eq!(
+      tokenize("<style>p > a { content: \"</b>\" }</STYLE>"),
+      vec![
+        tag("style", &[], false),
+        Token::Text("p > a { content: \"</b>\" }".to_string()),
+        Token::End("style".to_string()),
+      ]
+    );
+
+    let (typst, warnings) = convert(&["<script>if (a < b) { x(\"<i>\") }</script>after"]);
+
+    assert_eq!(typst, "after");
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn whitespace_and_escaping() {
+    let (typst, _) = convert(&["<span>a \n  *b*  #c</span>"]);
+
+    assert_eq!(typst, "a \\*b\\* \\#c");
+
+    let (typst, _) = convert(&["<p>- not a list</p>"]);
+
+    assert_eq!(typst, "\n\n\\- not a list\n\n");
+  }
+
+  #[test]
+  fn unsupported_elements() {
+    let (typst, warnings) = convert(&["<blink>a</blink><blink>b</blink><video>c</video>"]);
+
+    assert_eq!(typst, "ab");
+    assert_eq!(
+      warnings,
+      vec![
+        "HTML element `<blink>` is not supported, only its text is kept",
+        "HTML element `<video>` can't be rendered in a PDF and is left out"
+      ]
+    );
+  }
+
+  #[test]
+  fn table_head_and_spans() {
+    let (typst, warnings) = convert(&[r#"<table>
+  <thead><tr><th>A</th><th>B</th></tr></thead>
+  <tbody>
+    <tr><td colspan="2">x</td></tr>
+    <tr><td rowspan=2>y</td><td>z</td></tr>
+    <tr><td>w</td></tr>
+  </tbody>
+</table>"#]);
+
+    assert_eq!(
+      typst,
+      "\n#table(
+  columns: 2,
+  inset: 10pt,
+  table.header(
+    [#strong[A]],
+    [#strong[B]],
+  ),
+  table.cell(colspan: 2)[x],
+  table.cell(rowspan: 2)[y],
+  [z],
+  [w],
+)
+"
+    );
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn table_columns_with_row_spans() {
+    let (typst, _) = convert(&["<table><tr><td rowspan=\"2\">a<td>b</tr><tr><td>c<td>d</table>"]);
+
+    assert!(typst.contains("columns: 3,"), "{}", typst);
+
+    let (typst, _) = convert(&["<table><tr><td colspan=\"3\">a</tr><tr><td>b</table>"]);
+
+    assert!(typst.contains("columns: 3,"), "{}", typst);
+  }
+
+  #[test]
+  fn table_header_rows_of_th() {
+    let (typst, _) = convert(&["<table><tr><th>A<th align=\"right\">B<tr><td>1<td>2</table>"]);
+
+    assert_eq!(
+      typst,
+      "\n#table(
+  columns: 2,
+  inset: 10pt,
+  table.header(
+    [#strong[A]],
+    table.cell(align: right)[#strong[B]],
+  ),
+  [1],
+  [2],
+)
+"
+    );
+  }
+
+  #[test]
+  fn table_caption() {
+    let (typst, _) = convert(&["<table><caption>Cap</caption><tr><td>a</td></tr></table>"]);
+
+    assert_eq!(
+      typst,
+      "\n#figure(\n  table(\n    columns: 1,\n    inset: 10pt,\n    [a],\n  ),\n  caption: [Cap],\n)\n"
+    );
+  }
+}
@@ -0,0 +1,264 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use mdbook::renderer::RenderContext;
+use typst::utils::hash128;
+
+use crate::download::{self, PrintDownload};
+use crate::link;
+use crate::Config;
+
+/// The directory in the output images are copied to, named by a hash of
+/// their content so every image is copied once.
+const IMAGES_DIR: &str = "mdbook-typst-pdf/images";
+
+/// The local image files a chapter read, with their modification time when
+/// they were read (`None` if they were missing), and the files it wrote into
+/// the output, to tell when a cached conversion of the chapter is out of date.
+#[derive(Debug, Default)]
+pub struct ImageFiles {
+  read: RefCell<Vec<(PathBuf, Option<SystemTime>)>>,
+  written: RefCell<Vec<PathBuf>>,
+}
+
+impl ImageFiles {
+  fn record(&self, path: &Path) {
+    self
+      .read
+      .borrow_mut()
+      .push((path.to_path_buf(), modified(path)));
+  }
+
+  /// Record a file in the output the chapter refers to.
+  pub fn record_output(&self, path: &Path) {
+    self.written.borrow_mut().push(path.to_path_buf());
+  }
+
+  /// Whether none of the files read changed, appeared or disappeared since
+  /// they were read, and the files written are still there.
+  pub fn unchanged(&self) -> bool {
+    self
+      .read
+      .borrow()
+      .iter()
+      .all(|(path, time)| modified(path) == *time)
+      && self.written.borrow().iter().all(|path| path.exists())
+  }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Resolve an image the chapter at `source_path` refers to as `src` to a
+/// path relative to the output directory, copying, downloading or converting
+/// it there. Images that can't be used are warned about and `None` is
+/// returned. Local files and the copy in the output are recorded in `files`.
+pub fn resolve(
+  ctx: &RenderContext,
+  cfg: &Config,
+  source_path: &Path,
+  src: &str,
+  files: &ImageFiles,
+  warn: impl Fn(&str),
+) -> Result<Option<String>, anyhow::Error> {
+  let path = src.split(['?', '#']).next().unwrap_or_default();
+
+  let data = if src.starts_with("http://") || src.starts_with("https://") {
+    if cfg.offline {
+      warn(&format!("remote image `{}` is not downloaded offline", src));
+
+      return Ok(None);
+    }
+
+    let Some(data) = fetch(ctx, cfg, src, &warn)? else {
+      return Ok(None);
+    };
+
+    data
+  } else {
+    // Like mdBook, `/`-rooted paths are relative to the book's `src`
+    // directory and others to the chapter's.
+    let path = percent_decode(path);
+    let relative = match path.strip_prefix('/') {
+      Some(rooted) => PathBuf::from(rooted),
+      None => source_path.parent().unwrap_or(Path::new("")).join(&path),
+    };
+
+    let src_path = ctx
+      .root
+      .join(&ctx.config.book.src)
+      .join(link::normalize_path(&relative));
+
+    files.record(&src_path);
+
+    match fs::read(&src_path) {
+      Ok(data) => data,
+      Err(err) => {
+        warn(&format!(
+          "image `{}` could not be read: {}",
+          src_path.display(),
+          err
+        ));
+
+        return Ok(None);
+      }
+    }
+  };
+
+  let data = match sniff(&data) {
+    Some("webp") => match webp_to_png(&data) {
+      Ok(png) => png,
+      Err(err) => {
+        warn(&format!(
+          "WebP image `{}` could not be converted to PNG: {}",
+          src, err
+        ));
+
+        return Ok(None);
+      }
+    },
+    Some(format @ ("avif" | "bmp" | "tiff")) => {
+      warn(&format!(
+        "image `{}` is in a format Typst can't read ({}), convert it to PNG, JPEG, GIF or SVG",
+        src,
+        format.to_uppercase()
+      ));
+
+      return Ok(None);
+    }
+    _ => data,
+  };
+
+  let extension = sniff(&data).or_else(|| {
+    Path::new(path)
+      .extension()
+      .and_then(|extension| extension.to_str())
+  });
+
+  let path = match extension {
+    Some(extension) => format!("{}/{:032x}.{}", IMAGES_DIR, hash128(&data), extension),
+    None => format!("{}/{:032x}", IMAGES_DIR, hash128(&data)),
+  };
+
+  let dest_path = ctx.destination.join(&path);
+
+  files.record_output(&dest_path);
+
+  if !dest_path.exists() {
+    if let Some(dest_dir) = dest_path.parent() {
+      fs::create_dir_all(dest_dir)?;
+    }
+
+    fs::write(&dest_path, data)?;
+  }
+
+  Ok(Some(path))
+}
+
+/// Decode `%`-escaped bytes of a URL path, like spaces written as `%20`.
+fn percent_decode(path: &str) -> String {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+    match (bytes[i], escaped) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Download a remote image into the image cache, or take it from there.
+fn fetch(
+  ctx: &RenderContext,
+  cfg: &Config,
+  url: &str,
+  warn: impl Fn(&str),
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+  let cache_dir = match &cfg.image_cache_path {
+    Some(path) => ctx.root.join(path),
+    None => dirs::cache_dir()
+      .unwrap_or_else(std::env::temp_dir)
+      .join("mdbook-typst-pdf")
+      .join("images"),
+  };
+
+  let cache_path = cache_dir.join(format!("{:032x}", hash128(url)));
+
+  if let Ok(data) = fs::read(&cache_path) {
+    return Ok(Some(data));
+  }
+
+  let data = match download::downloader().download_with_progress(
+    url,
+    &mut PrintDownload(url, cfg.diagnostic_format.unwrap_or_default()),
+  ) {
+    Ok(data) => data,
+    Err(err) => {
+      warn(&format!(
+        "remote image `{}` could not be downloaded: {}",
+        url, err
+      ));
+
+      return Ok(None);
+    }
+  };
+
+  fs::create_dir_all(&cache_dir)?;
+  fs::write(&cache_path, &data)?;
+
+  Ok(Some(data))
+}
+
+/// The format of image data from its first bytes.
+fn sniff(data: &[u8]) -> Option<&'static str> {
+  let brand = data.get(4..12);
+
+  if data.starts_with(b"\x89PNG") {
+    Some("png")
+  } else if data.starts_with(b"\xFF\xD8\xFF") {
+    Some("jpg")
+  } else if data.starts_with(b"GIF8") {
+    Some("gif")
+  } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+    Some("webp")
+  } else if brand == Some(&b"ftypavif"[..]) || brand == Some(&b"ftypavis"[..]) {
+    Some("avif")
+  } else if data.starts_with(b"BM") {
+    Some("bmp")
+  } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+    Some("tiff")
+  } else if String::from_utf8_lossy(&data[..data.len().min(1024)]).contains("<svg") {
+    Some("svg")
+  } else {
+    None
+  }
+}
+
+/// Convert a WebP image, which Typst can't read, to PNG.
+fn webp_to_png(data: &[u8]) -> Result<Vec<u8>, image::ImageError> {
+  let image = image::load_from_memory_with_format(data, image::ImageFormat::WebP)?;
+
+  let mut png = Vec::new();
+  image.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+
+  Ok(png)
+}
@@ -0,0 +1,391 @@
+use mdbook::renderer::RenderContext;
+use mdbook::BookItem;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::convert;
+
+/// Resolves links between chapters to the Typst labels emitted for them.
+pub struct LinkResolver {
+  /// Chapters by their source path relative to the book's `src` directory.
+  chapters: HashMap<PathBuf, ChapterAnchors>,
+}
+
+/// The labels a chapter can be linked to.
+struct ChapterAnchors {
+  /// The chapter label.
+  label: String,
+  /// The ids of all headings in the chapter.
+  ids: HashSet<String>,
+}
+
+impl LinkResolver {
+  /// Collect the labels of every chapter in the book.
+  pub fn new(ctx: &RenderContext) -> Self {
+    let sources: Vec<_> = ctx
+      .book
+      .iter()
+      .filter_map(|item| match item {
+        BookItem::Chapter(ch) => Some((ch.source_path.as_ref()?, &ch.content)),
+        _ => None,
+      })
+      .collect();
+
+    // Paths that only differ in characters labels can't hold, like `a b.md`
+    // and `a_b.md`, get the same label. Later chapters get a number added.
+    let mut taken: HashSet<String> = sources
+      .iter()
+      .filter_map(|(source_path, _)| convert::chapter_label(source_path))
+      .collect();
+    let mut used = HashSet::new();
+
+    let mut chapters = HashMap::new();
+
+    for (source_path, content) in sources {
+      let path = normalize_path(source_path);
+
+      if chapters.contains_key(&path) {
+        continue;
+      }
+
+      let Some(label) = convert::chapter_label(source_path) else {
+        continue;
+      };
+
+      let label = if used.insert(label.clone()) {
+        label
+      } else {
+        let unique = (2..)
+          .map(|n| format!("{}-{}", label, n))
+          .find(|unique| !taken.contains(unique))
+          .unwrap_or_default();
+
+        taken.insert(unique.clone());
+
+        unique
+      };
+
+      chapters.insert(
+        path,
+        ChapterAnchors {
+          label,
+          ids: heading_ids(content),
+        },
+      );
+    }
+
+    Self { chapters }
+  }
+
+  /// The label of the chapter at `source_path`.
+  pub fn label(&self, source_path: &Path) -> Option<&str> {
+    self
+      .chapters
+      .get(&normalize_path(source_path))
+      .map(|chapter| chapter.label.as_str())
+  }
+
+  /// A hash of every label links can resolve to.
+  pub fn fingerprint(&self) -> u128 {
+    let mut anchors: Vec<_> = self
+      .chapters
+      .iter()
+      .map(|(path, chapter)| {
+        let mut ids: Vec<_> = chapter.ids.iter().collect();
+        ids.sort();
+        (path, &chapter.label, ids)
+      })
+      .collect();
+
+    anchors.sort();
+
+    typst::utils::hash128(&anchors)
+  }
+
+  /// Resolve `dest_url` as written in the chapter at `source_path` to the
+  /// label it points to.
+  pub fn resolve(&self, source_path: &Path, dest_url: &str) -> Result<String, LinkError> {
+    let (path, fragment) = match dest_url.split_once('#') {
+      Some((path, fragment)) => (path, Some(fragment)),
+      None => (dest_url, None),
+    };
+
+    let path = path.split('?').next().unwrap_or_default();
+
+    let target = if path.is_empty() {
+      normalize_path(source_path)
+    } else if let Some(path) = path.strip_prefix('/') {
+      normalize_path(Path::new(path))
+    } else {
+      normalize_path(&source_path.parent().unwrap_or(Path::new("")).join(path))
+    };
+
+    let chapter = self
+      .lookup(&target)
+      .ok_or_else(|| LinkError::ChapterNotFound(dest_url.to_string()))?;
+
+    match fragment {
+      None | Some("") => Ok(convert::chapter_anchor(&chapter.label)),
+      Some(fragment) => {
+        let id = mdbook::utils::normalize_id(fragment);
+
+        if chapter.ids.contains(&id) {
+          Ok(convert::heading_anchor(&chapter.label, &id))
+        } else {
+          Err(LinkError::AnchorNotFound(dest_url.to_string()))
+        }
+      }
+    }
+  }
+
+  /// Find a chapter by a link target, accepting the `.html` names mdBook
+  /// gives chapters as well as directory links to `index.md`/`README.md`.
+  fn lookup(&self, target: &Path) -> Option<&ChapterAnchors> {
+    let mut candidates = vec![];
+
+    match target.extension().and_then(|ext| ext.to_str()) {
+      Some("md") => candidates.push(target.to_path_buf()),
+      Some("html") | Some("htm") => candidates.push(target.with_extension("md")),
+      _ => {
+        candidates.push(target.join("index.md"));
+        candidates.push(target.join("README.md"));
+      }
+    }
+
+    if target.file_stem().and_then(|stem| stem.to_str()) == Some("index") {
+      candidates.push(target.with_file_name("README.md"));
+    }
+
+    candidates
+      .iter()
+      .find_map(|candidate| self.chapters.get(candidate))
+  }
+}
+
+/// A link that could not be resolved.
+#[derive(Debug)]
+pub enum LinkError {
+  /// The link does not point to a chapter of the book.
+  ChapterNotFound(String),
+  /// The chapter exists but has no heading with the given anchor.
+  AnchorNotFound(String),
+}
+
+impl fmt::Display for LinkError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LinkError::ChapterNotFound(url) => {
+        write!(f, "link `{}` does not point to a chapter of the book", url)
+      }
+      LinkError::AnchorNotFound(url) => {
+        write!(f, "link `{}` points to a heading that does not exist", url)
+      }
+    }
+  }
+}
+
+/// Lexically normalise a relative path, resolving `.` and `..`.
+pub fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+
+  for component in path.components() {
+    match component {
+      Component::ParentDir => {
+        normalized.pop();
+      }
+      Component::Normal(part) => normalized.push(part),
+      Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+    }
+  }
+
+  normalized
+}
+
+/// Collect the ids mdBook generates for the headings of a chapter.
+fn heading_ids(content: &str) -> HashSet<String> {
+  let mut ids = HashSet::new();
+  let mut heading: Option<String> = None;
+
+  for event in Parser::new_ext(content, convert::parser_options()) {
+    match event {
+      Event::Start(Tag::Heading { .. }) => heading = Some(String::new()),
+      Event::End(TagEnd::Heading(_)) => {
+        if let Some(text) = heading.take() {
+          ids.insert(mdbook::utils::normalize_id(&text));
+        }
+      }
+      Event::Text(t) | Event::Code(t) => {
+        if let Some(text) = heading.as_mut() {
+          text.push_str(&t);
+        }
+      }
+      _ => (),
+    }
+  }
+
+  ids
+}
+
+#[cfg(test)]
+mod tests {
+  use mdbook::book::{Book, Chapter};
+
+  use super::*;
+
+  fn resolver() -> LinkResolver {
+    let mut book = Book::new();
+
+    for (path, content) in [
+      ("README.md", "# Introduction\n\n## Getting Started\n"),
+      ("guide/index.md", "# Guide\n"),
+      ("guide/setup.md", "# Setup\n\n## Install `rustup`\n"),
+      ("guide/sub/deep.md", "# Deep\n"),
+      ("a b.md", "# A B\n"),
+      ("a_b.md", "# A_B\n"),
+      ("a_b-2.md", "# A_B 2\n"),
+    ] {
+      book.push_item(Chapter::new(path, content.to_string(), path, vec![]));
+    }
+
+    LinkResolver::new(&RenderContext::new(
+      "book",
+      book,
+      mdbook::Config::default(),
+      "book/out",
+    ))
+  }
+
+  fn resolve(source_path: &str, dest_url: &str) -> Result<String, LinkError> {
+    resolver().resolve(Path::new(source_path), dest_url)
+  }
+
+  #[test]
+  fn normalize() {
+    assert_eq!(normalize_path(Path::new("a/./b/../c")), Path::new("a/c"));
+    assert_eq!(normalize_path(Path::new("../a")), Path::new("a"));
+    assert_eq!(normalize_path(Path::new("/a/b")), Path::new("a/b"));
+  }
+
+  #[test]
+  fn relative_links() {
+    for (source_path, dest_url, label) in [
+      ("guide/setup.md", "sub/deep.md", "guide:sub:deep.html"),
+      ("guide/setup.md", "./sub/deep.md", "guide:sub:deep.html"),
+      ("guide/sub/deep.md", "../setup.md", "guide:setup.html"),
+      ("guide/sub/deep.md", "../../README.md", "README.html"),
+      ("guide/sub/deep.md", "/guide/setup.md", "guide:setup.html"),
+      ("README.md", "guide/setup.html", "guide:setup.html"),
+      ("README.md", "guide/setup.md?plain=1", "guide:setup.html"),
+    ] {
+      assert_eq!(
+        resolve(source_path, dest_url).unwrap(),
+        label,
+        "{} in {}",
+        dest_url,
+        source_path
+      );
+    }
+  }
+
+  #[test]
+  fn index_links() {
+    for (source_path, dest_url, label) in [
+      ("README.md", "guide/", "guide:index.html"),
+      ("README.md", "guide", "guide:index.html"),
+      ("README.md", "guide/index.html", "guide:index.html"),
+      ("guide/setup.md", "../index.md", "README.html"),
+      ("guide/setup.md", "../index.html", "README.html"),
+      ("guide/setup.md", "..", "README.html"),
+    ] {
+      assert_eq!(
+        resolve(source_path, dest_url).unwrap(),
+        label,
+        "{} in {}",
+        dest_url,
+        source_path
+      );
+    }
+  }
+
+  #[test]
+  fn fragment_links() {
+    for (source_path, dest_url, label) in [
+      (
+        "README.md",
+        "#getting-started",
+        "README.html-getting-started",
+      ),
+      ("README.md", "#", "README.html"),
+      (
+        "README.md",
+        "guide/setup.md#install-rustup",
+        "guide:setup.html-install-rustup",
+      ),
+      (
+        "README.md",
+        "guide/setup.html?x=1#Install-Rustup",
+        "guide:setup.html-install-rustup",
+      ),
+    ] {
+      assert_eq!(
+        resolve(source_path, dest_url).unwrap(),
+        label,
+        "{} in {}",
+        dest_url,
+        source_path
+      );
+    }
+  }
+
+  #[test]
+  fn unresolvable_links() {
+    assert!(matches!(
+      resolve("README.md", "missing.md"),
+      Err(LinkError::ChapterNotFound(_))
+    ));
+    assert!(matches!(
+      resolve("guide/setup.md", "README.md"),
+      Err(LinkError::ChapterNotFound(_))
+    ));
+    assert!(matches!(
+      resolve("README.md", "img/"),
+      Err(LinkError::ChapterNotFound(_))
+    ));
+    assert!(matches!(
+      resolve("README.md", "guide/setup.md#missing"),
+      Err(LinkError::AnchorNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn chapter_labels() {
+    assert_eq!(
+      convert::chapter_label(Path::new("part1/intro.md")).as_deref(),
+      Some("part1:intro")
+    );
+    assert_eq!(
+      convert::chapter_label(Path::new("a b(1).md")).as_deref(),
+      Some("a_b_1_")
+    );
+  }
+
+  #[test]
+  fn colliding_labels() {
+    let links = resolver();
+
+    assert_eq!(links.label(Path::new("a b.md")), Some("a_b"));
+    assert_eq!(links.label(Path::new("a_b.md")), Some("a_b-3"));
+    assert_eq!(links.label(Path::new("a_b-2.md")), Some("a_b-2"));
+
+    assert_eq!(
+      links.resolve(Path::new("README.md"), "a_b.md").unwrap(),
+      "a_b-3.html"
+    );
+    assert_eq!(
+      links.resolve(Path::new("README.md"), "a b.md").unwrap(),
+      "a_b.html"
+    );
+  }
+}
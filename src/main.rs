@@ -1,25 +1,43 @@
 mod args;
+mod book;
+mod code;
 mod convert;
+mod diagnostic;
 mod download;
 mod export;
+mod fonts;
+mod html;
+mod images;
+mod link;
+mod math;
 mod package;
+mod sourcemap;
+mod template;
 mod terminal;
+mod watch;
 mod world;
 
-use args::{FontArgs, PackageStorageArgs};
+use anyhow::anyhow;
+use clap::Parser;
 use codespan_reporting::term::{self, termcolor};
 use mdbook::config::Config as MdConfig;
 use mdbook::renderer::RenderContext;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::io::{self, IsTerminal, Write};
 use std::path::Path;
 use std::path::PathBuf;
-use tempfile::NamedTempFile;
 use termcolor::{ColorChoice, WriteColor};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::args::{Input, SharedArgs};
+use crate::args::{
+  CliArguments, Command, DiagnosticFormat, Input, PdfStandard, SharedArgs, WorldArgs,
+};
+use crate::code::{Badge, HiddenLines};
+use crate::convert::{ChapterCache, ImageCaption};
+use crate::sourcemap::SourceMap;
+use crate::template::{Template, TemplateVar};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
@@ -28,6 +46,24 @@ pub struct Config {
   pub custom_template: Option<String>,
   pub section_number: bool,
   pub chapter_no_pagebreak: bool,
+  pub chapter_endnotes: bool,
+  pub draft_placeholder: bool,
+  pub font_paths: Vec<PathBuf>,
+  pub ignore_system_fonts: bool,
+  pub package_path: Option<PathBuf>,
+  pub package_cache_path: Option<PathBuf>,
+  pub inputs: BTreeMap<String, String>,
+  pub creation_timestamp: Option<i64>,
+  pub pdf_standard: Option<PdfStandard>,
+  pub keywords: Vec<String>,
+  pub template_vars: BTreeMap<String, TemplateVar>,
+  pub diagnostic_format: Option<DiagnosticFormat>,
+  pub hidden_lines: HiddenLines,
+  pub badges: BTreeMap<String, Badge>,
+  pub image_caption: ImageCaption,
+  pub figure_no_numbering: bool,
+  pub image_cache_path: Option<PathBuf>,
+  pub offline: bool,
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -39,60 +75,231 @@ fn main() -> Result<(), anyhow::Error> {
     .with(tracing_subscriber::fmt::layer())
     .init();
 
-  let mut stdin = io::stdin();
+  match CliArguments::parse().command {
+    None => {
+      let mut stdin = io::stdin();
 
-  let ctx = RenderContext::from_json(&mut stdin)?;
+      let ctx = RenderContext::from_json(&mut stdin)?;
 
-  let cfg: Config = ctx
+      render(&ctx, &WorldArgs::default())
+    }
+    Some(Command::Build(command)) => {
+      let ctx = book::load(&command.book_dir, command.dest_dir.as_deref())?;
+
+      render(&ctx, &command.world_args)
+    }
+    Some(Command::Convert(command)) => {
+      let ctx = book::load(&command.book_dir, command.dest_dir.as_deref())?;
+
+      let cfg = load_config(&ctx, &command.world_args)?;
+
+      convert(&ctx, &cfg, &mut ChapterCache::default())?;
+
+      Ok(())
+    }
+    Some(Command::Compile(command)) => {
+      let output = match (command.output, &command.input) {
+        (Some(output), _) => output,
+        (None, Input::Path(path)) => path.with_extension("pdf"),
+        (None, Input::Stdin) => {
+          return Err(anyhow!(
+            "an output path is required when compiling from stdin"
+          ))
+        }
+      };
+
+      compile(
+        SharedArgs::new(command.input, output, command.world_args),
+        &SourceMap::default(),
+      )
+    }
+    Some(Command::Watch(command)) => watch::watch(&command),
+    Some(Command::Fonts(command)) => {
+      fonts::fonts(&command);
+
+      Ok(())
+    }
+  }
+}
+
+/// Convert the book to Typst and compile it to PDF if enabled.
+fn render(ctx: &RenderContext, world_args: &WorldArgs) -> Result<(), anyhow::Error> {
+  let cfg = load_config(ctx, world_args)?;
+
+  let (typst_filename, source_map) = convert(ctx, &cfg, &mut ChapterCache::default())?;
+
+  if cfg.pdf {
+    compile(
+      shared_args(ctx, &cfg, typst_filename, world_args)?,
+      &source_map,
+    )?;
+  }
+
+  Ok(())
+}
+
+/// Convert the book and write the Typst file and the book metadata module,
+/// returning the path of the Typst file and its source map.
+fn convert(
+  ctx: &RenderContext,
+  cfg: &Config,
+  cache: &mut ChapterCache,
+) -> Result<(PathBuf, SourceMap), anyhow::Error> {
+  let template_str = load_template(ctx, cfg)?;
+
+  let (typst_str, source_map) = convert::convert_typst(ctx, cfg, &template_str, cache)?;
+
+  let typst_filename = output_filename(&ctx.destination, &ctx.config, "typ");
+
+  fs::create_dir_all(&ctx.destination)?;
+
+  write_file(&typst_str, &typst_filename);
+
+  write_file(
+    &template::book_module(ctx, cfg)?,
+    &ctx.destination.join(template::BOOK_MODULE),
+  );
+
+  Ok((typst_filename, source_map))
+}
+
+/// Compile a Typst file to PDF, printing the error if it fails.
+fn compile(args: SharedArgs, source_map: &SourceMap) -> Result<(), anyhow::Error> {
+  if let Err(msg) = crate::export::export_pdf(args, source_map) {
+    print_error(&msg).expect("failed to print error");
+
+    return Err(anyhow!(msg));
+  }
+
+  Ok(())
+}
+
+/// Load the backend configuration. The diagnostic format and PDF standards
+/// given on the command line take precedence over the configured ones.
+fn load_config(ctx: &RenderContext, world_args: &WorldArgs) -> Result<Config, anyhow::Error> {
+  let mut cfg: Config = ctx
     .config
     .get_deserialized_opt("output.typst-pdf")?
     .unwrap_or_default();
 
-  let template_str = if let Some(custom_template) = &cfg.custom_template {
-    let mut custom_template_path = ctx.root.clone();
-    custom_template_path.push(custom_template);
-    std::fs::read_to_string(custom_template_path)?
-  } else {
-    include_str!("assets/template.typ").to_string()
-  };
+  if world_args.diagnostic_format.is_some() {
+    cfg.diagnostic_format = world_args.diagnostic_format;
+  }
 
-  let typst_str = convert::convert_typst(&ctx, &cfg, &template_str)?;
+  // All standards given on the command line are passed to the compiler, the
+  // converter only needs to know whether one of them requires alt text.
+  if let Some(&standard) = world_args
+    .pdf_standard
+    .iter()
+    .max_by_key(|standard| standard.requires_alt_text())
+  {
+    cfg.pdf_standard = Some(standard);
+  }
 
-  let typst_filename = output_filename(&ctx.destination, &ctx.config, "typ");
+  Ok(cfg)
+}
 
-  if cfg.pdf {
-    let mut tmpfile = NamedTempFile::new()?;
-    tmpfile.write_all(typst_str.as_bytes())?;
-    tmpfile.flush()?;
-
-    write_file(&typst_str, &typst_filename);
-
-    let args = SharedArgs {
-      input: Input::Path(typst_filename),
-      inputs: vec![],
-      output: output_filename(&ctx.destination, &ctx.config, "pdf"),
-      root: None,
-      font_args: FontArgs {
-        font_paths: vec![],
-        ignore_system_fonts: false,
-      },
-      creation_timestamp: None,
-      package_storage_args: PackageStorageArgs {
-        package_cache_path: None,
-        package_path: None,
-      },
+fn template_path(ctx: &RenderContext, cfg: &Config) -> Option<PathBuf> {
+  cfg
+    .custom_template
+    .as_ref()
+    .map(|custom_template| ctx.root.join(custom_template))
+}
+
+fn load_template(ctx: &RenderContext, cfg: &Config) -> Result<Template, anyhow::Error> {
+  match template_path(ctx, cfg) {
+    Some(custom_template_path) => {
+      let source = std::fs::read_to_string(&custom_template_path).map_err(|err| {
+        anyhow!(
+          "failed to read template {}: {}",
+          custom_template_path.display(),
+          err
+        )
+      })?;
+
+      Template::parse(source, &custom_template_path.display().to_string())
+    }
+    None => Template::parse(
+      include_str!("assets/template.typ").to_string(),
+      "assets/template.typ",
+    ),
+  }
+}
+
+/// The arguments for compiling the book's Typst file, combining the settings
+/// in `book.toml` with the ones given on the command line. Paths in
+/// `book.toml` are relative to the book root.
+fn shared_args(
+  ctx: &RenderContext,
+  cfg: &Config,
+  typst_filename: PathBuf,
+  world_args: &WorldArgs,
+) -> Result<SharedArgs, anyhow::Error> {
+  let mut world_args = world_args.clone();
+
+  // `SOURCE_DATE_EPOCH` takes precedence over the configured timestamp, as
+  // the command line reads it into `world_args` already.
+  if world_args.creation_timestamp.is_none() {
+    world_args.creation_timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+      Ok(epoch) => {
+        Some(args::parse_timestamp(&epoch).map_err(|err| anyhow!("SOURCE_DATE_EPOCH: {err}"))?)
+      }
+      Err(_) => cfg
+        .creation_timestamp
+        .map(|timestamp| args::parse_timestamp(&timestamp.to_string()))
+        .transpose()
+        .map_err(|err| anyhow!("creation-timestamp: {err}"))?,
     };
+  }
 
-    let res = crate::export::export_pdf(args);
+  let font_args = &mut world_args.font_args;
+  font_args
+    .font_paths
+    .splice(0..0, cfg.font_paths.iter().map(|path| ctx.root.join(path)));
+  font_args.ignore_system_fonts |= cfg.ignore_system_fonts;
 
-    if let Err(msg) = res {
-      print_error(&msg).expect("failed to print error");
+  let package_storage_args = &mut world_args.package_storage_args;
+  if package_storage_args.package_path.is_none() {
+    package_storage_args.package_path = cfg.package_path.as_ref().map(|path| ctx.root.join(path));
+  }
+  if package_storage_args.package_cache_path.is_none() {
+    package_storage_args.package_cache_path = cfg
+      .package_cache_path
+      .as_ref()
+      .map(|path| ctx.root.join(path));
+  }
 
-      return Err(anyhow::anyhow!(msg));
-    }
+  world_args.diagnostic_format = cfg.diagnostic_format;
+
+  if world_args.pdf_standard.is_empty() {
+    world_args.pdf_standard.extend(cfg.pdf_standard);
   }
 
-  Ok(())
+  // Inputs given on the command line take precedence.
+  world_args.inputs.splice(
+    0..0,
+    cfg
+      .inputs
+      .iter()
+      .map(|(key, value)| (key.clone(), value.clone())),
+  );
+
+  let mut args = SharedArgs::new(
+    Input::Path(typst_filename),
+    output_filename(&ctx.destination, &ctx.config, "pdf"),
+    world_args,
+  );
+
+  args.ident = Some(
+    ctx
+      .config
+      .book
+      .title
+      .clone()
+      .unwrap_or_else(|| "book".to_string()),
+  );
+
+  Ok(args)
 }
 
 fn color_stream() -> termcolor::StandardStream {
@@ -0,0 +1,1090 @@
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::borrow::Cow;
+
+/// LaTeX commands that map directly onto a Typst math symbol.
+const SYMBOLS: &[(&str, &str)] = &[
+  ("alpha", "alpha"),
+  ("beta", "beta"),
+  ("gamma", "gamma"),
+  ("delta", "delta"),
+  ("epsilon", "epsilon.alt"),
+  ("varepsilon", "epsilon"),
+  ("zeta", "zeta"),
+  ("eta", "eta"),
+  ("theta", "theta"),
+  ("vartheta", "theta.alt"),
+  ("iota", "iota"),
+  ("kappa", "kappa"),
+  ("varkappa", "kappa.alt"),
+  ("lambda", "lambda"),
+  ("mu", "mu"),
+  ("nu", "nu"),
+  ("xi", "xi"),
+  ("omicron", "omicron"),
+  ("pi", "pi"),
+  ("varpi", "pi.alt"),
+  ("rho", "rho"),
+  ("varrho", "rho.alt"),
+  ("sigma", "sigma"),
+  ("varsigma", "sigma.alt"),
+  ("tau", "tau"),
+  ("upsilon", "upsilon"),
+  ("phi", "phi.alt"),
+  ("varphi", "phi"),
+  ("chi", "chi"),
+  ("psi", "psi"),
+  ("omega", "omega"),
+  ("Gamma", "Gamma"),
+  ("Delta", "Delta"),
+  ("Theta", "Theta"),
+  ("Lambda", "Lambda"),
+  ("Xi", "Xi"),
+  ("Pi", "Pi"),
+  ("Sigma", "Sigma"),
+  ("Upsilon", "Upsilon"),
+  ("Phi", "Phi"),
+  ("Psi", "Psi"),
+  ("Omega", "Omega"),
+  ("times", "times"),
+  ("cdot", "dot.op"),
+  ("pm", "plus.minus"),
+  ("mp", "minus.plus"),
+  ("div", "div"),
+  ("ast", "ast.op"),
+  ("star", "star.op"),
+  ("circ", "compose"),
+  ("bullet", "bullet"),
+  ("oplus", "plus.circle"),
+  ("ominus", "minus.circle"),
+  ("otimes", "times.circle"),
+  ("odot", "dot.circle"),
+  ("setminus", "without"),
+  ("cup", "union"),
+  ("cap", "sect"),
+  ("bigcup", "union.big"),
+  ("bigcap", "sect.big"),
+  ("wedge", "and"),
+  ("land", "and"),
+  ("vee", "or"),
+  ("lor", "or"),
+  ("neg", "not"),
+  ("lnot", "not"),
+  ("ne", "eq.not"),
+  ("neq", "eq.not"),
+  ("le", "lt.eq"),
+  ("leq", "lt.eq"),
+  ("leqslant", "lt.eq.slant"),
+  ("ge", "gt.eq"),
+  ("geq", "gt.eq"),
+  ("geqslant", "gt.eq.slant"),
+  ("ll", "lt.double"),
+  ("gg", "gt.double"),
+  ("approx", "approx"),
+  ("equiv", "equiv"),
+  ("sim", "tilde.op"),
+  ("simeq", "tilde.eq"),
+  ("cong", "tilde.equiv"),
+  ("propto", "prop"),
+  ("prec", "prec"),
+  ("succ", "succ"),
+  ("in", "in"),
+  ("notin", "in.not"),
+  ("ni", "in.rev"),
+  ("subset", "subset"),
+  ("subseteq", "subset.eq"),
+  ("supset", "supset"),
+  ("supseteq", "supset.eq"),
+  ("forall", "forall"),
+  ("exists", "exists"),
+  ("nexists", "exists.not"),
+  ("emptyset", "emptyset"),
+  ("varnothing", "emptyset"),
+  ("infty", "infinity"),
+  ("partial", "diff"),
+  ("nabla", "nabla"),
+  ("sum", "sum"),
+  ("prod", "product"),
+  ("coprod", "product.co"),
+  ("int", "integral"),
+  ("iint", "integral.double"),
+  ("iiint", "integral.triple"),
+  ("oint", "integral.cont"),
+  ("to", "arrow.r"),
+  ("rightarrow", "arrow.r"),
+  ("leftarrow", "arrow.l"),
+  ("gets", "arrow.l"),
+  ("leftrightarrow", "arrow.l.r"),
+  ("Rightarrow", "arrow.r.double"),
+  ("Leftarrow", "arrow.l.double"),
+  ("Leftrightarrow", "arrow.l.r.double"),
+  ("implies", "arrow.r.double.long"),
+  ("impliedby", "arrow.l.double.long"),
+  ("iff", "arrow.l.r.double.long"),
+  ("longrightarrow", "arrow.r.long"),
+  ("longleftarrow", "arrow.l.long"),
+  ("mapsto", "arrow.r.bar"),
+  ("uparrow", "arrow.t"),
+  ("downarrow", "arrow.b"),
+  ("ldots", "dots.h"),
+  ("dots", "dots.h"),
+  ("cdots", "dots.h.c"),
+  ("vdots", "dots.v"),
+  ("ddots", "dots.down"),
+  ("perp", "perp"),
+  ("parallel", "parallel"),
+  ("mid", "divides"),
+  ("angle", "angle"),
+  ("triangle", "triangle.t"),
+  ("ell", "ell"),
+  ("hbar", "planck.reduce"),
+  ("Re", "Re"),
+  ("Im", "Im"),
+  ("aleph", "aleph"),
+  ("prime", "prime"),
+  ("colon", "colon"),
+  ("langle", "angle.l"),
+  ("rangle", "angle.r"),
+  ("lfloor", "floor.l"),
+  ("rfloor", "floor.r"),
+  ("lceil", "ceil.l"),
+  ("rceil", "ceil.r"),
+  ("vert", "bar.v"),
+  ("lvert", "bar.v"),
+  ("rvert", "bar.v"),
+  ("Vert", "bar.v.double"),
+  ("lVert", "bar.v.double"),
+  ("rVert", "bar.v.double"),
+  ("|", "bar.v.double"),
+  ("{", "brace.l"),
+  ("}", "brace.r"),
+  ("lbrace", "brace.l"),
+  ("rbrace", "brace.r"),
+  ("$", "\\$"),
+  ("#", "\\#"),
+  ("%", "%"),
+  ("&", "\\&"),
+  ("_", "\\_"),
+  (",", "thin"),
+  (":", "med"),
+  (">", "med"),
+  (";", "thick"),
+  (" ", "space"),
+  ("quad", "quad"),
+  ("qquad", "wide"),
+];
+
+/// Operator names that Typst math already knows under the same name.
+const OPERATORS: &[&str] = &[
+  "arccos", "arcsin", "arctan", "arg", "cos", "cosh", "cot", "coth", "csc", "deg", "det", "dim",
+  "exp", "gcd", "hom", "inf", "ker", "lg", "lim", "liminf", "limsup", "ln", "log", "max", "min",
+  "mod", "Pr", "sec", "sin", "sinh", "sup", "tan", "tanh",
+];
+
+/// Accent and decoration commands taking one argument.
+const ACCENTS: &[(&str, &str)] = &[
+  ("hat", "hat"),
+  ("widehat", "hat"),
+  ("bar", "macron"),
+  ("overline", "overline"),
+  ("underline", "underline"),
+  ("vec", "arrow"),
+  ("overrightarrow", "arrow"),
+  ("dot", "dot"),
+  ("ddot", "dot.double"),
+  ("tilde", "tilde"),
+  ("widetilde", "tilde"),
+  ("acute", "acute"),
+  ("grave", "grave"),
+  ("breve", "breve"),
+  ("check", "caron"),
+  ("overbrace", "overbrace"),
+  ("underbrace", "underbrace"),
+  ("cancel", "cancel"),
+  ("mathbf", "bold"),
+  ("boldsymbol", "bold"),
+  ("bm", "bold"),
+  ("mathrm", "upright"),
+  ("mathit", "italic"),
+  ("mathbb", "bb"),
+  ("mathcal", "cal"),
+  ("mathfrak", "frak"),
+  ("mathsf", "sans"),
+  ("mathtt", "mono"),
+];
+
+/// Commands whose argument is plain text rather than math.
+const TEXTS: &[(&str, Option<&str>)] = &[
+  ("text", None),
+  ("textrm", None),
+  ("textnormal", None),
+  ("mbox", None),
+  ("textbf", Some("bold")),
+  ("textit", Some("italic")),
+  ("texttt", Some("mono")),
+];
+
+/// Commands that only affect LaTeX's own layout and are dropped.
+const IGNORED: &[&str] = &[
+  "!",
+  "displaystyle",
+  "textstyle",
+  "scriptstyle",
+  "limits",
+  "nolimits",
+  "nonumber",
+  "notag",
+  "big",
+  "Big",
+  "bigg",
+  "Bigg",
+  "bigl",
+  "bigr",
+  "Bigl",
+  "Bigr",
+  "biggl",
+  "biggr",
+  "Biggl",
+  "Biggr",
+];
+
+/// The result of translating one LaTeX formula.
+pub struct Math {
+  /// The formula as Typst math markup, without the surrounding `$`.
+  pub typst: String,
+  /// LaTeX commands and environments that could not be translated.
+  pub unknown: Vec<String>,
+}
+
+/// Translate a LaTeX formula as written for MathJax or KaTeX into Typst math.
+pub fn latex_to_typst(latex: &str) -> Math {
+  let mut translator = Translator {
+    tokens: tokenize(latex),
+    pos: 0,
+    unknown: Vec::new(),
+  };
+
+  let typst = translator.sequence(&|_| false);
+
+  Math {
+    typst,
+    unknown: translator.unknown,
+  }
+}
+
+/// Rewrite mdBook's MathJax delimiters `\\( ... \\)` and `\\[ ... \\]` into the
+/// `$ ... $` and `$$ ... $$` syntax pulldown-cmark understands.
+///
+/// Code spans, code blocks and HTML are left untouched. The formula itself has
+/// its Markdown backslash escapes resolved, the same way MathJax sees it in the
+/// HTML output.
+pub fn rewrite_mathjax_delimiters(content: &str) -> Rewritten<'_> {
+  if !content.contains("\\\\(") && !content.contains("\\\\[") {
+    return Rewritten {
+      content: Cow::Borrowed(content),
+      offsets: Vec::new(),
+    };
+  }
+
+  let verbatim_ranges: Vec<_> = Parser::new_ext(content, Options::empty())
+    .into_offset_iter()
+    .filter(|(event, _)| {
+      matches!(
+        event,
+        Event::Code(_)
+          | Event::Html(_)
+          | Event::InlineHtml(_)
+          | Event::Start(Tag::CodeBlock(_))
+          | Event::Start(Tag::HtmlBlock)
+      )
+    })
+    .map(|(_, range)| range)
+    .collect();
+
+  let mut output = String::with_capacity(content.len());
+  let mut offsets = Vec::new();
+  let mut copied = 0;
+  let mut pos = 0;
+
+  while let Some(offset) = content[pos..].find("\\\\") {
+    let start = pos + offset;
+    pos = start + 2;
+
+    let (close, delimiter, display) = match content[pos..].chars().next() {
+      Some('(') => ("\\\\)", "$", false),
+      Some('[') => ("\\\\]", "$$", true),
+      _ => continue,
+    };
+
+    if verbatim_ranges.iter().any(|range| range.contains(&start)) {
+      continue;
+    }
+
+    let body_start = start + 3;
+
+    let Some(len) = content[body_start..].find(close) else {
+      break;
+    };
+
+    let body = escape_dollars(&unescape_markdown(&content[body_start..body_start + len]));
+
+    output.push_str(&content[copied..start]);
+    offsets.push((output.len(), start));
+    output.push_str(delimiter);
+    output.push_str(if display { &body } else { body.trim() });
+    output.push_str(delimiter);
+
+    copied = body_start + len + close.len();
+    offsets.push((output.len(), copied));
+    pos = copied;
+  }
+
+  output.push_str(&content[copied..]);
+
+  Rewritten {
+    content: Cow::Owned(output),
+    offsets,
+  }
+}
+
+/// Markdown with its MathJax delimiters rewritten.
+pub struct Rewritten<'a> {
+  pub content: Cow<'a, str>,
+  /// Sorted `(rewritten, original)` offsets where a rewritten formula starts
+  /// or the copied text after it resumes.
+  offsets: Vec<(usize, usize)>,
+}
+
+impl Rewritten<'_> {
+  /// Map an offset in the rewritten content back to the original Markdown.
+  /// Offsets inside a formula map to its opening delimiter.
+  pub fn original(&self, offset: usize) -> usize {
+    let index = self
+      .offsets
+      .partition_point(|&(rewritten, _)| rewritten <= offset);
+
+    match index
+      .checked_sub(1)
+      .map(|index| (index, self.offsets[index]))
+    {
+      None => offset,
+      // Even indices start a formula.
+      Some((index, (_, original))) if index % 2 == 0 => original,
+      Some((_, (rewritten, original))) => original + (offset - rewritten),
+    }
+  }
+}
+
+/// Escape the `$` signs of a formula, which would otherwise end the `$...$`
+/// it is written into. An escaped `\$` is already a LaTeX dollar sign.
+fn escape_dollars(latex: &str) -> String {
+  let mut escaped = String::with_capacity(latex.len());
+  let mut backslashes = 0;
+
+  for ch in latex.chars() {
+    if ch == '$' && backslashes % 2 == 0 {
+      escaped.push('\\');
+    }
+
+    backslashes = if ch == '\\' { backslashes + 1 } else { 0 };
+    escaped.push(ch);
+  }
+
+  escaped
+}
+
+/// Resolve Markdown backslash escapes of ASCII punctuation.
+fn unescape_markdown(text: &str) -> String {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut chars = text.chars().peekable();
+
+  while let Some(ch) = chars.next() {
+    if ch == '\\' {
+      if let Some(&next) = chars.peek() {
+        if next.is_ascii_punctuation() {
+          unescaped.push(next);
+          chars.next();
+          continue;
+        }
+      }
+    }
+
+    unescaped.push(ch);
+  }
+
+  unescaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+  /// A control sequence without the leading backslash, e.g. `frac` or `,`.
+  Command(&'a str),
+  /// `\\`
+  Newline,
+  /// `{`
+  Open,
+  /// `}`
+  Close,
+  /// `^`
+  Sup,
+  /// `_`
+  Sub,
+  /// `&`
+  Align,
+  /// Whitespace.
+  Space(&'a str),
+  /// A run of digits, possibly with a decimal point.
+  Number(&'a str),
+  /// Any other single character.
+  Char(char),
+}
+
+impl Token<'_> {
+  /// The LaTeX source of this token, used to recover text arguments.
+  fn source(&self) -> String {
+    match self {
+      Token::Command(name) => format!("\\{}", name),
+      Token::Newline => "\\\\".to_string(),
+      Token::Open => "{".to_string(),
+      Token::Close => "}".to_string(),
+      Token::Sup => "^".to_string(),
+      Token::Sub => "_".to_string(),
+      Token::Align => "&".to_string(),
+      Token::Space(s) | Token::Number(s) => s.to_string(),
+      Token::Char(ch) => ch.to_string(),
+    }
+  }
+}
+
+fn tokenize(latex: &str) -> Vec<Token<'_>> {
+  let mut tokens = Vec::new();
+  let mut rest = latex;
+
+  while let Some(ch) = rest.chars().next() {
+    let len = match ch {
+      '%' => {
+        // Comments run until the end of the line.
+        rest.find('\n').unwrap_or(rest.len())
+      }
+      '\\' => match rest[1..].chars().next() {
+        Some('\\') => {
+          tokens.push(Token::Newline);
+          2
+        }
+        Some(c) if c.is_ascii_alphabetic() => {
+          let len = rest[1..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len() - 1);
+          tokens.push(Token::Command(&rest[1..1 + len]));
+          1 + len
+        }
+        Some(c) => {
+          tokens.push(Token::Command(&rest[1..1 + c.len_utf8()]));
+          1 + c.len_utf8()
+        }
+        None => 1,
+      },
+      '{' => {
+        tokens.push(Token::Open);
+        1
+      }
+      '}' => {
+        tokens.push(Token::Close);
+        1
+      }
+      '^' => {
+        tokens.push(Token::Sup);
+        1
+      }
+      '_' => {
+        tokens.push(Token::Sub);
+        1
+      }
+      '&' => {
+        tokens.push(Token::Align);
+        1
+      }
+      c if c.is_whitespace() => {
+        let len = rest
+          .find(|c: char| !c.is_whitespace())
+          .unwrap_or(rest.len());
+        tokens.push(Token::Space(&rest[..len]));
+        len
+      }
+      c if c.is_ascii_digit() => {
+        let len = rest
+          .find(|c: char| !c.is_ascii_digit() && c != '.')
+          .unwrap_or(rest.len());
+        tokens.push(Token::Number(&rest[..len]));
+        len
+      }
+      c => {
+        tokens.push(Token::Char(c));
+        c.len_utf8()
+      }
+    };
+
+    rest = &rest[len..];
+  }
+
+  tokens
+}
+
+struct Translator<'a> {
+  tokens: Vec<Token<'a>>,
+  pos: usize,
+  unknown: Vec<String>,
+}
+
+impl<'a> Translator<'a> {
+  fn peek(&self) -> Option<Token<'a>> {
+    self.tokens.get(self.pos).copied()
+  }
+
+  fn bump(&mut self) -> Option<Token<'a>> {
+    let token = self.peek();
+    self.pos += 1;
+    token
+  }
+
+  fn skip_spaces(&mut self) {
+    while let Some(Token::Space(_)) = self.peek() {
+      self.pos += 1;
+    }
+  }
+
+  /// Translate atoms until `stop` matches the next token or the input ends.
+  /// The stopping token is not consumed.
+  fn sequence(&mut self, stop: &dyn Fn(Token) -> bool) -> String {
+    let mut atoms: Vec<String> = Vec::new();
+
+    loop {
+      self.skip_spaces();
+
+      let Some(token) = self.peek() else {
+        break;
+      };
+
+      if stop(token) {
+        break;
+      }
+
+      let mut atom = match token {
+        // A script without a base, like `{}^{14}C`.
+        Token::Sup | Token::Sub => "\"\"".to_string(),
+        _ => self.atom(),
+      };
+
+      // Attach sub- and superscripts to the atom just translated.
+      loop {
+        self.skip_spaces();
+
+        let marker = match self.peek() {
+          Some(Token::Sup) => "^",
+          Some(Token::Sub) => "_",
+          Some(Token::Char('\'')) => {
+            self.pos += 1;
+            atom.push('\'');
+            continue;
+          }
+          _ => break,
+        };
+
+        self.pos += 1;
+
+        if atom.is_empty() {
+          atom.push_str("\"\"");
+        }
+
+        let script = self.argument();
+
+        write_group(&mut atom, marker, &script);
+      }
+
+      if !atom.is_empty() {
+        atoms.push(atom);
+      }
+    }
+
+    atoms.join(" ")
+  }
+
+  /// Read one argument: a braced group or a single atom.
+  fn argument(&mut self) -> String {
+    self.skip_spaces();
+
+    match self.peek() {
+      Some(Token::Open) => {
+        self.pos += 1;
+        let inner = self.sequence(&|t| t == Token::Close);
+        self.pos += 1;
+        inner
+      }
+      // Like any single token argument, a number argument is one digit, as
+      // in `\frac12` or `x^23`.
+      Some(Token::Number(number)) if number.len() > 1 => {
+        let (digit, rest) = number.split_at(1);
+        self.tokens[self.pos] = Token::Number(rest);
+        digit.to_string()
+      }
+      Some(_) => self.atom(),
+      None => String::new(),
+    }
+  }
+
+  /// Read a braced argument verbatim, e.g. the name of an environment.
+  fn raw_argument(&mut self) -> String {
+    self.skip_spaces();
+
+    if self.peek() != Some(Token::Open) {
+      return self.bump().map(|t| t.source()).unwrap_or_default();
+    }
+
+    self.pos += 1;
+
+    let mut raw = String::new();
+    let mut depth = 0;
+
+    while let Some(token) = self.bump() {
+      match token {
+        Token::Open => depth += 1,
+        Token::Close if depth == 0 => break,
+        Token::Close => depth -= 1,
+        _ => (),
+      }
+
+      raw.push_str(&token.source());
+    }
+
+    raw
+  }
+
+  /// Read an optional `[...]` argument.
+  fn optional_argument(&mut self) -> Option<String> {
+    self.skip_spaces();
+
+    if self.peek() != Some(Token::Char('[')) {
+      return None;
+    }
+
+    self.pos += 1;
+    let inner = self.sequence(&|t| t == Token::Char(']'));
+    self.pos += 1;
+
+    Some(inner)
+  }
+
+  /// Translate a single atom, including the arguments of a command.
+  fn atom(&mut self) -> String {
+    let Some(token) = self.bump() else {
+      return String::new();
+    };
+
+    match token {
+      Token::Command(name) => self.command(name),
+      Token::Open => {
+        let inner = self.sequence(&|t| t == Token::Close);
+        self.pos += 1;
+        inner
+      }
+      Token::Close | Token::Space(_) => String::new(),
+      Token::Newline => "\\\n".to_string(),
+      Token::Align => "&".to_string(),
+      Token::Sup => "^".to_string(),
+      Token::Sub => "_".to_string(),
+      Token::Number(number) => number.to_string(),
+      Token::Char(ch) => escape_char(ch),
+    }
+  }
+
+  fn command(&mut self, name: &str) -> String {
+    if let Some((_, symbol)) = SYMBOLS.iter().find(|(n, _)| *n == name) {
+      return symbol.to_string();
+    }
+
+    if OPERATORS.contains(&name) {
+      return name.to_string();
+    }
+
+    if IGNORED.contains(&name) {
+      return String::new();
+    }
+
+    if let Some((_, func)) = ACCENTS.iter().find(|(n, _)| *n == name) {
+      let body = self.argument();
+      return format!("{}({})", func, body);
+    }
+
+    if let Some((_, style)) = TEXTS.iter().find(|(n, _)| *n == name) {
+      let text = format!("\"{}\"", escape_string(&self.raw_argument()));
+      return match style {
+        Some(style) => format!("{}({})", style, text),
+        None => text,
+      };
+    }
+
+    match name {
+      "frac" | "dfrac" | "tfrac" | "cfrac" => {
+        let num = self.argument();
+        let denom = self.argument();
+        format!("frac({}, {})", or_empty(&num), or_empty(&denom))
+      }
+      "binom" | "dbinom" | "tbinom" => {
+        let n = self.argument();
+        let k = self.argument();
+        format!("binom({}, {})", or_empty(&n), or_empty(&k))
+      }
+      "sqrt" => match self.optional_argument() {
+        Some(index) => {
+          let radicand = self.argument();
+          format!("root({}, {})", or_empty(&index), or_empty(&radicand))
+        }
+        None => format!("sqrt({})", or_empty(&self.argument())),
+      },
+      "operatorname" => {
+        let limits = if self.peek() == Some(Token::Char('*')) {
+          self.pos += 1;
+          ", limits: #true"
+        } else {
+          ""
+        };
+        let op = escape_string(&self.raw_argument());
+        format!("op(\"{}\"{})", op, limits)
+      }
+      "left" => {
+        let open = self.delimiter();
+        let inner = self.sequence(&|t| t == Token::Command("right"));
+        self.pos += 1;
+        let close = self.delimiter();
+        format!("lr({} {} {})", open, inner, close)
+      }
+      "right" => {
+        // Unbalanced `\right`, keep the delimiter.
+        self.delimiter()
+      }
+      "label" | "tag" => {
+        self.raw_argument();
+        String::new()
+      }
+      "not" => {
+        self.skip_spaces();
+        let negated = self.atom();
+        match negated.as_str() {
+          "=" => "eq.not".to_string(),
+          "in" | "subset" | "supset" | "subset.eq" | "supset.eq" | "exists" | "lt.eq" | "gt.eq" => {
+            format!("{}.not", negated)
+          }
+          _ => format!("cancel({})", or_empty(&negated)),
+        }
+      }
+      "begin" => self.environment(),
+      "end" => {
+        self.raw_argument();
+        String::new()
+      }
+      _ => {
+        self.unknown.push(format!("\\{}", name));
+        format!("\"\\\\{}\"", escape_string(name))
+      }
+    }
+  }
+
+  /// Translate the delimiter following `\left`, `\right` or a `\big` command.
+  fn delimiter(&mut self) -> String {
+    self.skip_spaces();
+
+    match self.bump() {
+      Some(Token::Char('.')) | None => String::new(),
+      Some(Token::Char(ch)) => format!("\\{}", ch),
+      Some(Token::Command("{")) | Some(Token::Command("lbrace")) => "\\{".to_string(),
+      Some(Token::Command("}")) | Some(Token::Command("rbrace")) => "\\}".to_string(),
+      Some(Token::Command(name)) => self.command(name),
+      Some(_) => String::new(),
+    }
+  }
+
+  /// Translate an environment after its `\begin`.
+  fn environment(&mut self) -> String {
+    let name = self.raw_argument();
+
+    let delim = match name.as_str() {
+      "matrix" | "smallmatrix" | "array" => Some("#none"),
+      "pmatrix" => Some("\"(\""),
+      "bmatrix" => Some("\"[\""),
+      "Bmatrix" => Some("\"{\""),
+      "vmatrix" => Some("\"|\""),
+      "Vmatrix" => Some("\"||\""),
+      _ => None,
+    };
+
+    if name == "array" {
+      // Skip the column specification.
+      self.raw_argument();
+    }
+
+    match (delim, name.as_str()) {
+      (Some(delim), _) => {
+        let rows = self.rows(true);
+        let rows: Vec<String> = rows
+          .into_iter()
+          .map(|cells| {
+            cells
+              .iter()
+              .map(|cell| or_empty(cell).to_string())
+              .collect::<Vec<_>>()
+              .join(", ")
+          })
+          .collect();
+        format!("mat(delim: {}, {})", delim, rows.join("; "))
+      }
+      (None, "cases" | "dcases") => {
+        let rows: Vec<String> = self
+          .rows(false)
+          .into_iter()
+          .map(|cells| cells.join(" "))
+          .collect();
+        format!("cases({})", rows.join(", "))
+      }
+      (
+        None,
+        "aligned" | "align" | "align*" | "alignat" | "alignat*" | "gather" | "gather*" | "gathered"
+        | "equation" | "equation*" | "split" | "multline" | "multline*" | "eqnarray" | "eqnarray*",
+      ) => {
+        let body = self.sequence(&|t| t == Token::Command("end"));
+        self.pos += 1;
+        self.raw_argument();
+        body
+      }
+      _ => {
+        self.unknown.push(format!("\\begin{{{}}}", name));
+        let body = self.sequence(&|t| t == Token::Command("end"));
+        self.pos += 1;
+        self.raw_argument();
+        body
+      }
+    }
+  }
+
+  /// Read rows separated by `\\` until `\end{...}`. When `split_cells` is
+  /// set, `&` separates cells, otherwise it is kept as an alignment point.
+  fn rows(&mut self, split_cells: bool) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+
+    loop {
+      let cell = self.sequence(&|t| {
+        t == Token::Newline || t == Token::Command("end") || (split_cells && t == Token::Align)
+      });
+
+      cells.push(cell);
+
+      match self.bump() {
+        Some(Token::Align) if split_cells => (),
+        Some(Token::Align) => cells.push("&".to_string()),
+        Some(Token::Newline) => rows.push(std::mem::take(&mut cells)),
+        _ => {
+          self.raw_argument();
+          break;
+        }
+      }
+    }
+
+    // A trailing `\\` before `\end` does not start another row.
+    if cells.iter().any(|cell| !cell.is_empty()) {
+      rows.push(cells);
+    }
+
+    rows
+  }
+}
+
+/// Append a sub- or superscript, grouping it in parentheses when needed.
+fn write_group(atom: &mut String, marker: &str, script: &str) {
+  atom.push_str(marker);
+
+  if script.chars().count() == 1 || script.chars().all(|c| c.is_ascii_digit()) {
+    atom.push_str(script);
+  } else {
+    atom.push('(');
+    atom.push_str(script);
+    atom.push(')');
+  }
+}
+
+/// Typst rejects empty function arguments, so fall back to an empty string.
+fn or_empty(s: &str) -> &str {
+  if s.is_empty() {
+    "\"\""
+  } else {
+    s
+  }
+}
+
+/// Escape a single LaTeX character for Typst math.
+fn escape_char(ch: char) -> String {
+  match ch {
+    // Typst math treats these as syntax rather than literal characters.
+    '/' | ',' | ';' | '"' | '#' | '$' | '@' | '\\' => format!("\\{}", ch),
+    '~' => "space.nobreak".to_string(),
+    _ => ch.to_string(),
+  }
+}
+
+/// Escape text for a Typst string literal.
+fn escape_string(text: &str) -> String {
+  text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn translation() {
+    let cases = [
+      (r"\frac{a}{b}", "frac(a, b)"),
+      (r"\dfrac12", "frac(1, 2)"),
+      (r"\binom{n}{k}", "binom(n, k)"),
+      (r"\sqrt{x}", "sqrt(x)"),
+      (r"\sqrt[3]{x}", "root(3, x)"),
+      (r"\sqrt[n]{x + 1}", "root(n, x + 1)"),
+      (r"x^2", "x^2"),
+      (r"x^23", "x^2 3"),
+      (r"a_1", "a_1"),
+      (r"e^{-x}", "e^(- x)"),
+      (r"x_{i}^{n+1}", "x_i^(n + 1)"),
+      (r"{}^{14}C", "\"\"^14 C"),
+      (r"f'(x)", "f' ( x )"),
+      (r"\sum_{i=1}^{n} i", "sum_(i = 1)^n i"),
+      (r"\int_0^1 f(x)\,dx", "integral_0^1 f ( x ) thin d x"),
+      (r"\alpha + \beta = \Gamma", "alpha + beta = Gamma"),
+      (
+        r"\phi \varphi \epsilon \varepsilon",
+        "phi.alt phi epsilon.alt epsilon",
+      ),
+      (r"x \le y \neq z", "x lt.eq y eq.not z"),
+      (r"\infty", "infinity"),
+      (r"\{a\}", "brace.l a brace.r"),
+      (r"\hat{x} \cdot \vec{v}", "hat(x) dot.op arrow(v)"),
+      (r"\overline{z}", "overline(z)"),
+      (r"\sin x + \log y", "sin x + log y"),
+      (r"\operatorname{rank} A", "op(\"rank\") A"),
+      (r"\mathbb{R}", "bb(R)"),
+      (r"\mathrm{d}x", "upright(d) x"),
+      (r"\text{if } x", "\"if \" x"),
+      (r"a \quad b", "a quad b"),
+      (r"ab", "a b"),
+      (
+        r"\begin{matrix} a & b \\ c & d \end{matrix}",
+        "mat(delim: #none, a, b; c, d)",
+      ),
+      (
+        r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}",
+        "mat(delim: \"(\", 1, 0; 0, 1)",
+      ),
+      (r"\begin{bmatrix} a \end{bmatrix}", "mat(delim: \"[\", a)"),
+      (
+        r"\begin{aligned} a &= b \\ c &= d \end{aligned}",
+        "a & = b \\\n c & = d",
+      ),
+      (
+        r"f(x) = \begin{cases} 1 & x > 0 \\ 0 & \text{otherwise} \end{cases}",
+        "f ( x ) = cases(1 & x > 0, 0 & \"otherwise\")",
+      ),
+      (r"\left( \frac{a}{b} \right)", "lr(\\( frac(a, b) \\))"),
+      (r"\left\{ x \right.", "lr(\\{ x )"),
+    ];
+
+    for (latex, typst) in cases {
+      let math = latex_to_typst(latex);
+
+      assert_eq!(math.typst, typst, "{}", latex);
+      assert!(math.unknown.is_empty(), "{}: {:?}", latex, math.unknown);
+    }
+  }
+
+  #[test]
+  fn unknown_commands() {
+    let cases: [(&str, &str, &[&str]); 4] = [
+      (r"\unknowncmd", "\"\\\\unknowncmd\"", &[r"\unknowncmd"]),
+      (
+        r"\foo{x} + \bar{y}",
+        "\"\\\\foo\" x + macron(y)",
+        &[r"\foo"],
+      ),
+      (r"\begin{weird} x \end{weird}", "x", &[r"\begin{weird}"]),
+      (
+        r"\foo + \baz",
+        "\"\\\\foo\" + \"\\\\baz\"",
+        &[r"\foo", r"\baz"],
+      ),
+    ];
+
+    for (latex, typst, unknown) in cases {
+      let math = latex_to_typst(latex);
+
+      assert_eq!(math.typst, typst, "{}", latex);
+      assert_eq!(math.unknown, unknown, "{}", latex);
+    }
+  }
+
+  #[test]
+  fn mathjax_delimiters() {
+    let cases = [
+      (r"no math", "no math"),
+      (r"inline \\( x^2 \\) math", "inline $x^2$ math"),
+      (
+        "display\n\\\\[\na \\\\\\\\ b\n\\\\]\n",
+        "display\n$$\na \\\\ b\n$$\n",
+      ),
+      (r"`\\( code \\)`", r"`\\( code \\)`"),
+      (r"\\( a \_ b \\)", "$a _ b$"),
+      (r"\\( \$5 + x \\) after", r"$\$5 + x$ after"),
+      (r"\\( \\$ \\)", r"$\$$"),
+    ];
+
+    for (markdown, rewritten) in cases {
+      assert_eq!(
+        rewrite_mathjax_delimiters(markdown).content,
+        rewritten,
+        "{}",
+        markdown
+      );
+    }
+  }
+
+  #[test]
+  fn mathjax_dollar_sign() {
+    let rewritten = rewrite_mathjax_delimiters(r"costs \\( \$5 + x \\) in *total*");
+    let events: Vec<_> = Parser::new_ext(&rewritten.content, Options::ENABLE_MATH).collect();
+
+    assert_eq!(
+      events[..4],
+      [
+        Event::Start(Tag::Paragraph),
+        Event::Text("costs ".into()),
+        Event::InlineMath(r"\$5 + x".into()),
+        Event::Text(" in ".into()),
+      ]
+    );
+    assert_eq!(latex_to_typst(r"\$5 + x").typst, r"\$ 5 + x");
+  }
+
+  #[test]
+  fn mathjax_offsets() {
+    let markdown = "a \\\\( x \\\\) b\n\\\\[\ny\n\\\\]\nc *d*";
+    let rewritten = rewrite_mathjax_delimiters(markdown);
+    let content = &rewritten.content;
+    assert_eq!(content, "a $x$ b\n$$\ny\n$$\nc *d*");
+
+    for text in ["a ", " b", "c", "*d*"] {
+      let offset = content.find(text).unwrap();
+      assert_eq!(&markdown[rewritten.original(offset)..][..text.len()], text);
+    }
+
+    assert_eq!(rewritten.original(content.find('x').unwrap()), 2);
+    assert_eq!(
+      rewritten.original(content.find('y').unwrap()),
+      markdown.find('\n').unwrap() + 1
+    );
+  }
+}
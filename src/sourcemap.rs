@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+/// Maps byte offsets in the generated Typst file back to the Markdown they
+/// were converted from.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+  /// The Markdown sources of the mapped chapters.
+  chapters: Vec<Chapter>,
+  /// Where mappings start in the generated text, sorted by offset.
+  spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone)]
+struct Chapter {
+  /// The chapter's path relative to the book root.
+  path: PathBuf,
+  /// The Markdown the chapter was parsed from.
+  content: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Span {
+  /// The offset in the generated text the mapping starts at.
+  generated: usize,
+  /// The chapter index and Markdown offset, `None` for text that was not
+  /// converted from Markdown.
+  source: Option<(usize, usize)>,
+}
+
+/// A position in a chapter's Markdown.
+pub struct Location<'a> {
+  pub path: &'a Path,
+  /// The 1-based line number.
+  pub line: usize,
+  /// The 1-based column, counted in characters.
+  pub column: usize,
+  /// The text of the line.
+  pub text: &'a str,
+}
+
+impl SourceMap {
+  /// An empty map for text converted from the chapter at `path`.
+  pub fn chapter(path: PathBuf, content: String) -> Self {
+    Self {
+      chapters: vec![Chapter { path, content }],
+      spans: vec![],
+    }
+  }
+
+  /// Map the text generated from `generated` on to `offset` in the Markdown
+  /// of the last chapter.
+  pub fn map(&mut self, generated: usize, offset: usize) {
+    let source = self
+      .chapters
+      .len()
+      .checked_sub(1)
+      .map(|chapter| (chapter, offset));
+
+    self.push(Span { generated, source });
+  }
+
+  /// Stop mapping the text generated from `generated` on.
+  pub fn unmap(&mut self, generated: usize) {
+    self.push(Span {
+      generated,
+      source: None,
+    });
+  }
+
+  /// Account for `len` bytes inserted before all mapped text.
+  pub fn shift(&mut self, len: usize) {
+    for span in &mut self.spans {
+      span.generated += len;
+    }
+  }
+
+  /// Add the mappings of text that was appended at offset `base`.
+  pub fn append(&mut self, other: SourceMap, base: usize) {
+    let chapters = self.chapters.len();
+
+    self.chapters.extend(other.chapters);
+
+    for span in other.spans {
+      self.push(Span {
+        generated: base + span.generated,
+        source: span
+          .source
+          .map(|(chapter, offset)| (chapters + chapter, offset)),
+      });
+    }
+  }
+
+  /// Find the Markdown the generated text at `generated` was converted from.
+  pub fn lookup(&self, generated: usize) -> Option<Location<'_>> {
+    let index = self
+      .spans
+      .partition_point(|span| span.generated <= generated)
+      .checked_sub(1)?;
+
+    let (chapter, offset) = self.spans[index].source?;
+    let chapter = &self.chapters[chapter];
+    let content = chapter.content.as_str();
+
+    let before = content.get(..offset)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[offset..]
+      .find('\n')
+      .map_or(content.len(), |i| offset + i);
+
+    Some(Location {
+      path: &chapter.path,
+      line: before.matches('\n').count() + 1,
+      column: before[line_start..].chars().count() + 1,
+      text: content[line_start..line_end].trim_end_matches('\r'),
+    })
+  }
+
+  /// Add a mapping, replacing those for text that was since truncated.
+  fn push(&mut self, span: Span) {
+    while self
+      .spans
+      .last()
+      .is_some_and(|last| last.generated >= span.generated)
+    {
+      self.spans.pop();
+    }
+
+    self.spans.push(span);
+  }
+}
@@ -0,0 +1,231 @@
+use anyhow::anyhow;
+use mdbook::renderer::RenderContext;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::process::Command;
+
+use crate::convert::typst_string;
+use crate::Config;
+
+/// The module templates import the book metadata from.
+pub const BOOK_MODULE: &str = "mdbook.typ";
+
+/// Declares the placeholder syntax a template is written against.
+const VERSION_MARKER: &str = "/**** MDBOOK_TYPST_PDF_VERSION ";
+/// Where version 2 templates bind the book content to `body`.
+pub const BODY_MARKER: &str = "/**** MDBOOK_TYPST_PDF_BODY ****/";
+/// Where the book content is inserted into version 1 templates.
+pub const PLACEHOLDER: &str = "/**** MDBOOK_TYPST_PDF_PLACEHOLDER ****/";
+/// Replaced by the book title in version 1 templates.
+pub const TITLE_PLACEHOLDER: &str = "MDBOOK_TYPST_PDF_TITLE";
+
+/// The placeholder syntax of a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateVersion {
+  /// The title and the content are inserted at `MDBOOK_TYPST_PDF_TITLE` and
+  /// `/**** MDBOOK_TYPST_PDF_PLACEHOLDER ****/`.
+  V1,
+  /// The metadata is imported from `mdbook.typ` and the content is bound to
+  /// `body` at `/**** MDBOOK_TYPST_PDF_BODY ****/`.
+  V2,
+}
+
+/// A template that has the placeholders its version requires.
+#[derive(Debug)]
+pub struct Template {
+  pub source: String,
+  pub version: TemplateVersion,
+}
+
+impl Template {
+  /// Check the placeholders of the template called `name`. Templates without
+  /// a `/**** MDBOOK_TYPST_PDF_VERSION n ****/` declaration are version 2 if
+  /// they have a body marker and version 1 otherwise.
+  pub fn parse(source: String, name: &str) -> Result<Self, anyhow::Error> {
+    let declared = match source.find(VERSION_MARKER) {
+      Some(start) => {
+        let rest = &source[start + VERSION_MARKER.len()..];
+        let version = rest.split_once(" ****/").map(|(version, _)| version.trim());
+
+        match version {
+          Some("1") => Some(TemplateVersion::V1),
+          Some("2") => Some(TemplateVersion::V2),
+          Some(version) => {
+            return Err(anyhow!(
+              "template {} declares unsupported placeholder syntax version `{}`, expected 1 or 2",
+              name,
+              version
+            ))
+          }
+          None => {
+            return Err(anyhow!(
+              "template {} has an unterminated `{}` declaration",
+              name,
+              VERSION_MARKER.trim_end()
+            ))
+          }
+        }
+      }
+      None => None,
+    };
+
+    let version = declared.unwrap_or(if source.contains(BODY_MARKER) {
+      TemplateVersion::V2
+    } else {
+      TemplateVersion::V1
+    });
+
+    let marker = match version {
+      TemplateVersion::V1 => PLACEHOLDER,
+      TemplateVersion::V2 => BODY_MARKER,
+    };
+
+    match source.matches(marker).count() {
+      0 => {
+        return Err(anyhow!(
+          "template {} is missing `{}`, which marks where the book content goes",
+          name,
+          marker
+        ))
+      }
+      1 => (),
+      count => {
+        return Err(anyhow!(
+          "template {} contains `{}` {} times, expected exactly once",
+          name,
+          marker,
+          count
+        ))
+      }
+    }
+
+    if version == TemplateVersion::V1 {
+      tracing::warn!(
+        "template {} uses the deprecated `{}` placeholders, replace them with `#import \"{}\": book` and `{}`",
+        name,
+        PLACEHOLDER,
+        BOOK_MODULE,
+        BODY_MARKER
+      );
+    }
+
+    Ok(Self { source, version })
+  }
+}
+
+/// A value of the `[output.typst-pdf.template-vars]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TemplateVar {
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+  Array(Vec<TemplateVar>),
+  Table(BTreeMap<String, TemplateVar>),
+}
+
+impl TemplateVar {
+  /// Write the value as a Typst expression.
+  fn to_typst(&self) -> String {
+    match self {
+      TemplateVar::Bool(value) => value.to_string(),
+      TemplateVar::Int(value) => value.to_string(),
+      TemplateVar::Float(value) => format!("{:?}", value),
+      TemplateVar::String(value) => typst_string(value),
+      TemplateVar::Array(values) => {
+        let values: String = values
+          .iter()
+          .map(|value| format!("{}, ", value.to_typst()))
+          .collect();
+
+        format!("({})", values)
+      }
+      TemplateVar::Table(table) => typst_dict(
+        table
+          .iter()
+          .map(|(key, value)| (key.as_str(), value.to_typst())),
+      ),
+    }
+  }
+}
+
+/// Generate the module that exposes the book metadata to templates as the
+/// `book` dictionary.
+pub fn book_module(ctx: &RenderContext, cfg: &Config) -> Result<String, anyhow::Error> {
+  let book = &ctx.config.book;
+
+  let optional = |value: Option<&String>| value.map_or("none".to_string(), |v| typst_string(v));
+
+  let authors: String = book
+    .authors
+    .iter()
+    .map(|author| format!("{}, ", typst_string(author)))
+    .collect();
+
+  let vars = cfg
+    .template_vars
+    .iter()
+    .map(|(key, value)| (key.as_str(), value.to_typst()));
+
+  let fields = [
+    ("title", optional(book.title.as_ref())),
+    ("authors", format!("({})", authors)),
+    ("description", optional(book.description.as_ref())),
+    ("language", optional(book.language.as_ref())),
+    ("mdbook-version", typst_string(&ctx.version)),
+    // Follows the creation timestamp of the compilation.
+    ("build-date", "datetime.today()".to_string()),
+    ("git-revision", optional(git_revision(ctx).as_ref())),
+    ("vars", typst_dict(vars)),
+  ];
+
+  let mut module = String::new();
+
+  writeln!(module, "// Generated by mdbook-typst-pdf.")?;
+  writeln!(module)?;
+  writeln!(
+    module,
+    "#let book = {}",
+    typst_dict(fields.iter().map(|(key, value)| (*key, value.clone())))
+  )?;
+
+  Ok(module)
+}
+
+/// Write key-value pairs as a Typst dictionary.
+fn typst_dict<'a>(entries: impl Iterator<Item = (&'a str, String)>) -> String {
+  let mut dict = String::from("(");
+
+  for (key, value) in entries {
+    let _ = write!(dict, "\n  {}: {},", typst_string(key), value);
+  }
+
+  if dict.len() == 1 {
+    dict.push(':');
+  } else {
+    dict.push('\n');
+  }
+
+  dict.push(')');
+
+  dict
+}
+
+/// The commit the book is built from, if it is in a git repository.
+fn git_revision(ctx: &RenderContext) -> Option<String> {
+  let output = Command::new("git")
+    .args(["rev-parse", "HEAD"])
+    .current_dir(&ctx.root)
+    .output()
+    .ok()?;
+
+  if !output.status.success() {
+    return None;
+  }
+
+  let revision = String::from_utf8(output.stdout).ok()?;
+
+  Some(revision.trim().to_string())
+}
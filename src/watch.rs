@@ -0,0 +1,199 @@
+use anyhow::anyhow;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::args::{BookCommand, WorldArgs};
+use crate::book;
+use crate::convert::ChapterCache;
+use crate::export;
+use crate::world::SystemWorld;
+
+/// How long to wait for further changes before rebuilding.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Build the book and rebuild it whenever one of its inputs changes.
+pub fn watch(command: &BookCommand) -> Result<(), anyhow::Error> {
+  let book_dir = command.book_dir.canonicalize()?;
+  let book_toml = book_dir.join("book.toml");
+
+  let (tx, rx) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(tx)?;
+
+  // Watch the book configuration and the default source directory even if the
+  // first build fails before the real inputs are known.
+  let mut watched = HashMap::new();
+  update_watched(
+    &mut watcher,
+    &mut watched,
+    vec![
+      (book_toml.clone(), RecursiveMode::NonRecursive),
+      (book_dir.join("src"), RecursiveMode::Recursive),
+    ],
+  );
+
+  let mut session = Session {
+    book_dir,
+    dest_dir: command.dest_dir.clone(),
+    world_args: command.world_args.clone(),
+    cache: ChapterCache::default(),
+    world: None,
+    destination: None,
+  };
+
+  let mut reload = true;
+
+  loop {
+    match session.build(reload) {
+      Ok(mut inputs) => {
+        inputs.push((book_toml.clone(), RecursiveMode::NonRecursive));
+        update_watched(&mut watcher, &mut watched, inputs);
+      }
+      Err(err) => crate::print_error(&err.to_string())?,
+    }
+
+    tracing::info!("Watching for changes");
+
+    let changed = wait_for_changes(&rx, session.destination.as_deref())?;
+
+    reload = changed.contains(&book_toml);
+  }
+}
+
+/// State kept between builds.
+struct Session {
+  /// The root directory of the book.
+  book_dir: PathBuf,
+  /// The output directory given on the command line.
+  dest_dir: Option<PathBuf>,
+  /// Arguments for compiling the converted Typst file.
+  world_args: WorldArgs,
+  /// Chapters converted by the previous build.
+  cache: ChapterCache,
+  /// The world of the previous compilation, reused for comemo's caches.
+  world: Option<SystemWorld>,
+  /// The output directory, which is written to by the build itself.
+  destination: Option<PathBuf>,
+}
+
+impl Session {
+  /// Convert and compile the book, returning the paths it depends on.
+  ///
+  /// With `reload`, the world is created from scratch to pick up changes to
+  /// the book configuration.
+  fn build(&mut self, reload: bool) -> Result<Vec<(PathBuf, RecursiveMode)>, anyhow::Error> {
+    let ctx = book::load(&self.book_dir, self.dest_dir.as_deref())?;
+    let cfg = crate::load_config(&ctx, &self.world_args)?;
+
+    let mut inputs = vec![(
+      ctx.root.join(&ctx.config.book.src),
+      RecursiveMode::Recursive,
+    )];
+
+    if let Some(template_path) = crate::template_path(&ctx, &cfg) {
+      inputs.push((template_path, RecursiveMode::NonRecursive));
+    }
+
+    let (typst_filename, source_map) = crate::convert(&ctx, &cfg, &mut self.cache)?;
+
+    let destination = ctx.destination.canonicalize()?;
+
+    self.destination = Some(destination.clone());
+
+    if !cfg.pdf {
+      return Ok(inputs);
+    }
+
+    let args = crate::shared_args(&ctx, &cfg, typst_filename, &self.world_args)?;
+
+    let mut world = match self.world.take() {
+      Some(world) if !reload => world,
+      _ => SystemWorld::new(&args).map_err(|err| anyhow!("{err}"))?,
+    };
+
+    world.reset();
+
+    let result = export::compile_once(&world, &args, &source_map);
+
+    comemo::evict(10);
+
+    // Files in the output directory are written by the build itself.
+    inputs.extend(
+      world
+        .dependencies()
+        .filter(|path| !path.starts_with(&destination))
+        .map(|path| (path, RecursiveMode::NonRecursive)),
+    );
+
+    self.world = Some(world);
+
+    if let Err(msg) = result {
+      crate::print_error(&msg)?;
+    }
+
+    Ok(inputs)
+  }
+}
+
+/// Watch exactly the given paths.
+fn update_watched(
+  watcher: &mut RecommendedWatcher,
+  watched: &mut HashMap<PathBuf, RecursiveMode>,
+  inputs: Vec<(PathBuf, RecursiveMode)>,
+) {
+  let inputs: HashMap<PathBuf, RecursiveMode> = inputs.into_iter().collect();
+
+  for path in watched.keys() {
+    if !inputs.contains_key(path) {
+      let _ = watcher.unwatch(path);
+    }
+  }
+
+  for (path, mode) in &inputs {
+    if watched.get(path) != Some(mode) {
+      if let Err(err) = watcher.watch(path, *mode) {
+        tracing::warn!("failed to watch {}: {}", path.display(), err);
+      }
+    }
+  }
+
+  *watched = inputs;
+}
+
+/// Block until a watched file changes and return the changed paths. Changes
+/// arriving in quick succession are collected into one rebuild.
+fn wait_for_changes(
+  rx: &mpsc::Receiver<notify::Result<Event>>,
+  ignore: Option<&Path>,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+  let mut changed = Vec::new();
+
+  loop {
+    let event = if changed.is_empty() {
+      rx.recv()?
+    } else {
+      match rx.recv_timeout(DEBOUNCE) {
+        Ok(event) => event,
+        Err(mpsc::RecvTimeoutError::Timeout) => break,
+        Err(err) => return Err(err.into()),
+      }
+    };
+
+    let event = event?;
+
+    if matches!(event.kind, EventKind::Access(_)) {
+      continue;
+    }
+
+    changed.extend(
+      event
+        .paths
+        .into_iter()
+        .filter(|path| ignore.is_none_or(|ignore| !path.starts_with(ignore))),
+    );
+  }
+
+  Ok(changed)
+}
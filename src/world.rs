@@ -18,7 +18,7 @@ use typst_kit::fonts::{FontSlot, Fonts};
 use typst_kit::package::PackageStorage;
 use typst_timing::{timed, TimingScope};
 
-use crate::args::{Input, SharedArgs};
+use crate::args::{DiagnosticFormat, Input, SharedArgs};
 use crate::download::PrintDownload;
 use crate::package;
 
@@ -44,6 +44,8 @@ pub struct SystemWorld {
   slots: Mutex<HashMap<FileId, FileSlot>>,
   /// Holds information about where packages are stored.
   package_storage: PackageStorage,
+  /// How package download progress is reported.
+  diagnostic_format: DiagnosticFormat,
   /// The current datetime if requested. This is stored here to ensure it is
   /// always the same within one compilation.
   /// Reset between compilations if not [`Now::Fixed`].
@@ -114,6 +116,7 @@ impl SystemWorld {
       fonts: fonts.fonts,
       slots: Mutex::new(HashMap::new()),
       package_storage: package::storage(&command.package_storage_args),
+      diagnostic_format: command.diagnostic_format,
       now,
     })
   }
@@ -133,6 +136,34 @@ impl SystemWorld {
     self.workdir.as_deref().unwrap_or(Path::new("."))
   }
 
+  /// Return all paths the last compilation depended on.
+  pub fn dependencies(&mut self) -> impl Iterator<Item = PathBuf> + '_ {
+    self
+      .slots
+      .get_mut()
+      .values()
+      .filter(|slot| slot.accessed())
+      .filter_map(|slot| {
+        system_path(
+          &self.root,
+          slot.id,
+          &self.package_storage,
+          self.diagnostic_format,
+        )
+        .ok()
+      })
+  }
+
+  /// Reset the compilation state in preparation of a new compilation.
+  pub fn reset(&mut self) {
+    for slot in self.slots.get_mut().values_mut() {
+      slot.reset();
+    }
+    if let Now::System(time) = &mut self.now {
+      time.take();
+    }
+  }
+
   /// Lookup a source file by id.
   #[track_caller]
   pub fn lookup(&self, id: FileId) -> Source {
@@ -156,11 +187,15 @@ impl World for SystemWorld {
   }
 
   fn source(&self, id: FileId) -> FileResult<Source> {
-    self.slot(id, |slot| slot.source(&self.root, &self.package_storage))
+    self.slot(id, |slot| {
+      slot.source(&self.root, &self.package_storage, self.diagnostic_format)
+    })
   }
 
   fn file(&self, id: FileId) -> FileResult<Bytes> {
-    self.slot(id, |slot| slot.file(&self.root, &self.package_storage))
+    self.slot(id, |slot| {
+      slot.file(&self.root, &self.package_storage, self.diagnostic_format)
+    })
   }
 
   fn font(&self, index: usize) -> Option<Font> {
@@ -223,14 +258,27 @@ impl FileSlot {
     }
   }
 
+  /// Whether the file was accessed in the ongoing compilation.
+  fn accessed(&self) -> bool {
+    self.source.accessed() || self.file.accessed()
+  }
+
+  /// Marks the file as not yet accessed in preparation of the next
+  /// compilation.
+  fn reset(&mut self) {
+    self.source.reset();
+    self.file.reset();
+  }
+
   /// Retrieve the source for this file.
   fn source(
     &mut self,
     project_root: &Path,
     package_storage: &PackageStorage,
+    diagnostic_format: DiagnosticFormat,
   ) -> FileResult<Source> {
     self.source.get_or_init(
-      || read(self.id, project_root, package_storage),
+      || read(self.id, project_root, package_storage, diagnostic_format),
       |data, prev| {
         let name = if prev.is_some() {
           "reparsing file"
@@ -250,9 +298,14 @@ impl FileSlot {
   }
 
   /// Retrieve the file's bytes.
-  fn file(&mut self, project_root: &Path, package_storage: &PackageStorage) -> FileResult<Bytes> {
+  fn file(
+    &mut self,
+    project_root: &Path,
+    package_storage: &PackageStorage,
+    diagnostic_format: DiagnosticFormat,
+  ) -> FileResult<Bytes> {
     self.file.get_or_init(
-      || read(self.id, project_root, package_storage),
+      || read(self.id, project_root, package_storage, diagnostic_format),
       |data, _| Ok(data.into()),
     )
   }
@@ -278,6 +331,17 @@ impl<T: Clone> SlotCell<T> {
     }
   }
 
+  /// Whether the cell was accessed in the ongoing compilation.
+  fn accessed(&self) -> bool {
+    self.accessed
+  }
+
+  /// Marks the cell as not yet accessed in preparation of the next
+  /// compilation.
+  fn reset(&mut self) {
+    self.accessed = false;
+  }
+
   /// Gets the contents of the cell or initialize them.
   fn get_or_init(
     &mut self,
@@ -316,13 +380,14 @@ fn system_path(
   project_root: &Path,
   id: FileId,
   package_storage: &PackageStorage,
+  diagnostic_format: DiagnosticFormat,
 ) -> FileResult<PathBuf> {
   // Determine the root path relative to which the file path
   // will be resolved.
   let buf;
   let mut root = project_root;
   if let Some(spec) = id.package() {
-    buf = package_storage.prepare_package(spec, &mut PrintDownload(&spec))?;
+    buf = package_storage.prepare_package(spec, &mut PrintDownload(&spec, diagnostic_format))?;
     root = &buf;
   }
 
@@ -335,11 +400,21 @@ fn system_path(
 ///
 /// If the ID represents stdin it will read from standard input,
 /// otherwise it gets the file path of the ID and reads the file from disk.
-fn read(id: FileId, project_root: &Path, package_storage: &PackageStorage) -> FileResult<Vec<u8>> {
+fn read(
+  id: FileId,
+  project_root: &Path,
+  package_storage: &PackageStorage,
+  diagnostic_format: DiagnosticFormat,
+) -> FileResult<Vec<u8>> {
   if id == *STDIN_ID {
     read_from_stdin()
   } else {
-    read_from_disk(&system_path(project_root, id, package_storage)?)
+    read_from_disk(&system_path(
+      project_root,
+      id,
+      package_storage,
+      diagnostic_format,
+    )?)
   }
 }
 